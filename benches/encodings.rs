@@ -0,0 +1,81 @@
+//! Compares the byte encodings on the cost of the operations the machines run
+//! on every character, and on the size of the keys each one needs.
+//!
+//! Run with `cargo bench --bench encodings`.
+
+use std::borrow::Borrow;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tfhe::shortint::{parameters::PARAM_MESSAGE_8_CARRY_0, prelude::*};
+use tfhe_regex::{
+    gen_bits_keys, EncodedCipher2bits, EncodedCipher4bits, EncodedCipher8bits, EncodedCipherBits,
+    EncodedCipherOneHot, SelectCipherTrait,
+};
+
+const ITERATIONS: u32 = 10;
+
+fn average<R>(mut f: impl FnMut() -> R) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+// `bootstraps` gives the number of programmable bootstraps of the operations,
+// for the encodings where a timing alone would hide it
+fn bench_encoding<T>(name: &str, gen_keys: impl FnOnce() -> (T::ClientKey, T::ServerKey), bootstraps: Option<&str>)
+where
+    T: SelectCipherTrait + Clone,
+    T::ClientKey: Serialize,
+    T::ServerKey: Serialize,
+{
+    let start = Instant::now();
+    let (client_key, server_key) = gen_keys();
+    let keygen = start.elapsed();
+    let client_key_size = bincode::serialize(&client_key).unwrap().len();
+    let server_key_size = bincode::serialize(&server_key).unwrap().len();
+
+    let left = T::encrypt(&client_key, b'q');
+    let right = T::encrypt(&client_key, b'r');
+    let mut class = [false; 256];
+    class[b'a' as usize..=b'z' as usize].fill(true);
+    let condition = client_key.borrow().encrypt(1);
+
+    println!("{}", name);
+    let row = |label: &str, value: String| println!("  {:<24} {}", label, value);
+    row("key generation", format!("{:?}", keygen));
+    row("client key", format!("{} bytes", client_key_size));
+    row("server key", format!("{} bytes", server_key_size));
+    row("encrypt", format!("{:?}", average(|| T::encrypt(&client_key, b'q'))));
+    row("equal", format!("{:?}", average(|| left.clone().equal(&server_key, right.clone()))));
+    row(
+        "greater_or_equal",
+        format!("{:?}", average(|| left.clone().greater_or_equal(&server_key, right.clone()))),
+    );
+    row("scalar_equal", format!("{:?}", average(|| left.clone().scalar_equal(&server_key, b'r'))));
+    row(
+        "scalar_greater_or_equal",
+        format!("{:?}", average(|| left.clone().scalar_greater_or_equal(&server_key, b'r'))),
+    );
+    row("in_table", format!("{:?}", average(|| left.clone().in_table(&server_key, &class))));
+    row("select", format!("{:?}", average(|| left.clone().select(&server_key, &condition, b'*'))));
+    if let Some(bootstraps) = bootstraps {
+        row("bootstraps", bootstraps.to_string());
+    }
+}
+
+fn main() {
+    bench_encoding::<EncodedCipher2bits>("EncodedCipher2bits", || gen_keys(PARAM_MESSAGE_2_CARRY_2), None);
+    bench_encoding::<EncodedCipher4bits>("EncodedCipher4bits", || gen_keys(PARAM_MESSAGE_4_CARRY_4), None);
+    // no carry space: comparing two ciphertexts takes several univariate lookups
+    bench_encoding::<EncodedCipher8bits>(
+        "EncodedCipher8bits",
+        || gen_keys(PARAM_MESSAGE_8_CARRY_0),
+        Some("equal 6, greater_or_equal 7, select 4, scalar and in_table 1"),
+    );
+    bench_encoding::<EncodedCipherOneHot>("EncodedCipherOneHot", || gen_keys(PARAM_MESSAGE_2_CARRY_2), None);
+    // gate bootstrapping, with the match bits on PARAM_MESSAGE_2_CARRY_2
+    bench_encoding::<EncodedCipherBits>("EncodedCipherBits", || gen_bits_keys(PARAM_MESSAGE_2_CARRY_2), None);
+}
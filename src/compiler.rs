@@ -1,24 +1,465 @@
+use std::fmt;
+
+use regex_syntax::ast::{self, Ast, Span};
 use regex_syntax::hir::{
-    visit, Anchor, Class, Hir, HirKind, Literal, RepetitionKind, RepetitionRange, Visitor,
+    self, visit, Anchor, Class, Group, GroupKind, Hir, HirKind, Literal, Repetition,
+    RepetitionKind, RepetitionRange, Visitor, WordBoundary,
 };
-use regex_syntax::Parser;
 
-use crate::program::{Action, Instruction, IntervalCharOptions, Program, ProgramItem};
+use regex_syntax::utf8::Utf8Sequences;
+
+use crate::dfa::Dfa;
+use crate::nfa::{ByteRange, Look, Nfa, State};
+use crate::program::{Action, CompiledSet, Instruction, IntervalCharOptions, Program, ProgramItem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+    StartLine,
+    EndLine,
+    WordBoundary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    Parse(Box<regex_syntax::Error>),
+    Unsupported { construct: Construct, span: Span },
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Construct::StartLine => write!(f, "start of line anchor"),
+            Construct::EndLine => write!(f, "end of line anchor"),
+            Construct::WordBoundary => write!(f, "word boundary"),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Parse(error) => write!(f, "{}", error),
+            CompileError::Unsupported { construct, span } => write!(
+                f,
+                "unsupported {} at {}..{}",
+                construct, span.start.offset, span.end.offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<ast::Error> for CompileError {
+    fn from(error: ast::Error) -> Self {
+        CompileError::Parse(Box::new(error.into()))
+    }
+}
+
+impl From<hir::Error> for CompileError {
+    fn from(error: hir::Error) -> Self {
+        CompileError::Parse(Box::new(error.into()))
+    }
+}
 
 pub struct Compiler {}
 
 impl Compiler {
-    pub fn compile(pattern: &str) -> Program {
-        let hir = Parser::new().parse(pattern).unwrap();
-        visit(&hir, ProgramFactory::default()).unwrap()
+    pub fn compile(pattern: &str) -> Result<Program, CompileError> {
+        let hir = parse(pattern, Backend::Program)?;
+        Ok(visit(&hir, ProgramFactory::default()).unwrap())
+    }
+
+    pub fn compile_nfa(pattern: &str) -> Result<Nfa, CompileError> {
+        let hir = parse(pattern, Backend::Nfa)?;
+        let mut factory = NfaFactory::default();
+        let end = factory.push(State::Match);
+        let start = factory.lower(&hir, end);
+        Ok(Nfa::new(factory.states, start))
+    }
+
+    pub fn compile_dfa(pattern: &str) -> Result<Dfa, CompileError> {
+        Ok(Dfa::new(&Self::compile_nfa(pattern)?))
+    }
+
+    /// Compiles several patterns into a single program, the leading characters
+    /// they have in common being shared.
+    pub fn compile_set(patterns: &[&str]) -> Result<CompiledSet, CompileError> {
+        let programs = patterns
+            .iter()
+            .map(|pattern| Self::compile(pattern))
+            .collect::<Result<Vec<Program>, CompileError>>()?;
+
+        // the common prefixes form a trie, the root being the empty prefix
+        let mut trie = vec![PrefixNode::default()];
+        for (index, program) in programs.iter().enumerate() {
+            let mut node = 0;
+            for item in program.iter().take(linear_prefix(program)) {
+                let child = trie[node].children.iter().copied().find(|child| {
+                    same_test(&trie[*child].item.as_ref().unwrap().instruction, &item.instruction)
+                });
+                node = match child {
+                    Some(child) => child,
+                    None => {
+                        let child = trie.len();
+                        trie.push(PrefixNode {
+                            item: Some(item.clone()),
+                            ..Default::default()
+                        });
+                        trie[node].children.push(child);
+                        child
+                    }
+                };
+            }
+            trie[node].patterns.push(index);
+        }
+
+        let mut factory = ProgramFactory::default();
+        factory.push_prefix_node(&trie, 0, 0, &programs);
+        Ok(CompiledSet::new(factory.program))
+    }
+}
+
+// A node of the trie of the prefixes of a set of programs: the instruction
+// leading to it, and the nodes and the patterns continuing from there.
+#[derive(Default)]
+struct PrefixNode {
+    item: Option<ProgramItem>,
+    children: Vec<usize>,
+    patterns: Vec<usize>,
+}
+
+// Number of leading instructions of the program that test the input in a
+// straight line, with no branch or jump leading into them. Other programs
+// starting with the same instructions can share them.
+fn linear_prefix(program: &Program) -> usize {
+    let first_target = program
+        .iter()
+        .filter_map(|item| match item.instruction {
+            Instruction::Branch(pc) | Instruction::Jump(pc) => Some(pc),
+            _ => None,
+        })
+        .min()
+        .unwrap_or(program.len());
+    let straight = program
+        .iter()
+        .take_while(|item| matches!(item.instruction, Instruction::Char(_) | Instruction::Start))
+        .count();
+    straight.min(first_target)
+}
+
+fn same_test(left: &Instruction, right: &Instruction) -> bool {
+    match (left, right) {
+        (Instruction::Char(left), Instruction::Char(right)) => left == right,
+        (Instruction::Start, Instruction::Start) => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Program,
+    Nfa,
+}
+
+fn parse(pattern: &str, backend: Backend) -> Result<Hir, CompileError> {
+    let ast = ast::parse::Parser::new().parse(pattern)?;
+    // byte classes such as `(?-u)[\x80-\xff]` may match invalid UTF-8
+    let hir = hir::translate::TranslatorBuilder::new()
+        .allow_invalid_utf8(true)
+        .build()
+        .translate(pattern, &ast)?;
+    let spans = ast::visit(&ast, Spans::new(ast.span()))?;
+    visit(&hir, Validator::new(&spans, backend))?;
+    Ok(hir)
+}
+
+// The HIR does not keep track of where its nodes come from. The translation
+// from the AST maps every assertion to one anchor or word boundary, in the
+// same order. Collecting the spans of those AST nodes in order is enough to
+// point at the HIR node an error is about.
+struct Spans {
+    pattern: Span,
+    assertions: Vec<Span>,
+}
+
+impl Spans {
+    fn new(pattern: &Span) -> Self {
+        Self {
+            pattern: *pattern,
+            assertions: vec![],
+        }
+    }
+}
+
+impl ast::Visitor for Spans {
+    type Output = Spans;
+    type Err = CompileError;
+
+    fn finish(self) -> Result<Self::Output, Self::Err> {
+        Ok(self)
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), Self::Err> {
+        if let Ast::Assertion(_) = ast {
+            self.assertions.push(*ast.span());
+        }
+        Ok(())
+    }
+}
+
+// Rejects the constructs a backend cannot lower.
+struct Validator<'a> {
+    spans: &'a Spans,
+    backend: Backend,
+    assertions: usize,
+}
+
+impl<'a> Validator<'a> {
+    fn new(spans: &'a Spans, backend: Backend) -> Self {
+        Self {
+            spans,
+            backend,
+            assertions: 0,
+        }
+    }
+
+    fn unsupported(&self, construct: Construct) -> CompileError {
+        let span = self.spans.assertions.get(self.assertions).copied().unwrap_or(self.spans.pattern);
+        CompileError::Unsupported { construct, span }
+    }
+}
+
+impl<'a> Visitor for Validator<'a> {
+    type Output = ();
+    type Err = CompileError;
+
+    fn finish(self) -> Result<Self::Output, Self::Err> {
+        Ok(())
+    }
+
+    fn visit_pre(&mut self, hir: &Hir) -> Result<(), Self::Err> {
+        match hir.kind() {
+            HirKind::Anchor(anchor) => {
+                match (anchor, self.backend) {
+                    (Anchor::StartLine, Backend::Nfa) => {
+                        return Err(self.unsupported(Construct::StartLine))
+                    }
+                    (Anchor::EndLine, Backend::Nfa) => {
+                        return Err(self.unsupported(Construct::EndLine))
+                    }
+                    _ => {}
+                }
+                self.assertions += 1;
+            }
+            HirKind::WordBoundary(_) => {
+                if self.backend == Backend::Nfa {
+                    return Err(self.unsupported(Construct::WordBoundary));
+                }
+                self.assertions += 1;
+            }
+            HirKind::Empty
+            | HirKind::Literal(_)
+            | HirKind::Class(_)
+            | HirKind::Repetition(_)
+            | HirKind::Group(_)
+            | HirKind::Concat(_)
+            | HirKind::Alternation(_) => {}
+        }
+        Ok(())
+    }
+}
+
+// UTF-8 encoding of a literal.
+fn literal_bytes(literal: &Literal) -> Vec<u8> {
+    match literal {
+        Literal::Unicode(c) => c.to_string().into_bytes(),
+        Literal::Byte(b) => vec![*b],
+    }
+}
+
+// One way for a class to match: its bytes one after the other, each of them
+// falling in any of the ranges.
+type ByteSequence = Vec<Vec<ByteRange>>;
+
+// UTF-8 encodings of the characters of a class. All the one-byte encodings are
+// gathered in the first sequence, the longer ones follow.
+fn class_sequences(class: &Class) -> Vec<ByteSequence> {
+    let encodings: Vec<Vec<ByteRange>> = match class {
+        Class::Unicode(set) => set
+            .ranges()
+            .iter()
+            .flat_map(|range| Utf8Sequences::new(range.start(), range.end()))
+            .map(|sequence| {
+                sequence
+                    .as_slice()
+                    .iter()
+                    .map(|range| ByteRange {
+                        start: range.start,
+                        end: range.end,
+                    })
+                    .collect()
+            })
+            .collect(),
+        Class::Bytes(set) => set
+            .ranges()
+            .iter()
+            .map(|range| {
+                vec![ByteRange {
+                    start: range.start(),
+                    end: range.end(),
+                }]
+            })
+            .collect(),
+    };
+    let mut single_bytes = vec![];
+    let mut sequences = vec![];
+    for encoding in encodings {
+        match encoding.len() {
+            1 => single_bytes.push(encoding[0]),
+            _ => sequences.push(encoding.into_iter().map(|range| vec![range]).collect()),
+        }
+    }
+    if !single_bytes.is_empty() {
+        sequences.insert(0, vec![single_bytes]);
     }
+    sequences
+}
+
+// Ranges of a class whose characters are all encoded on a single byte.
+fn class_ranges(class: &Class) -> Vec<ByteRange> {
+    class_sequences(class).into_iter().flatten().flatten().collect()
+}
+
+// Whether the expression is a literal or a class matching exactly one byte.
+fn is_single_byte(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Literal(literal) => literal_bytes(literal).len() == 1,
+        HirKind::Class(class) => class_sequences(class)
+            .iter()
+            .all(|sequence| sequence.len() == 1),
+        _ => false,
+    }
+}
+
+fn capture_index(group: &Group) -> Option<usize> {
+    match &group.kind {
+        GroupKind::CaptureIndex(index) | GroupKind::CaptureName { index, .. } => Some(*index as usize),
+        GroupKind::NonCapturing => None,
+    }
+}
+
+// Minimum and maximum (if any) number of repetitions.
+fn repetition_bounds(kind: &RepetitionKind) -> (u32, Option<u32>) {
+    match kind.clone() {
+        RepetitionKind::ZeroOrOne => (0, Some(1)),
+        RepetitionKind::ZeroOrMore => (0, None),
+        RepetitionKind::OneOrMore => (1, None),
+        RepetitionKind::Range(RepetitionRange::Exactly(n)) => (n, Some(n)),
+        RepetitionKind::Range(RepetitionRange::AtLeast(n)) => (n, None),
+        RepetitionKind::Range(RepetitionRange::Bounded(m, n)) => (m, Some(n)),
+    }
+}
+
+// Thompson construction. Each sub-expression is lowered in front of the state
+// that follows it, so the only state that needs patching is the loop head of
+// unbounded repetitions.
+#[derive(Default)]
+struct NfaFactory {
+    states: Vec<State>,
+}
+
+impl NfaFactory {
+    fn push(&mut self, state: State) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    fn lower(&mut self, hir: &Hir, next: usize) -> usize {
+        match hir.kind() {
+            HirKind::Empty => next,
+            HirKind::Literal(literal) => literal_bytes(literal)
+                .iter()
+                .rev()
+                .fold(next, |next, byte| self.push(State::Char { byte: *byte, next })),
+            HirKind::Class(class) => {
+                let targets: Vec<usize> = class_sequences(class)
+                    .into_iter()
+                    .map(|sequence| {
+                        sequence
+                            .into_iter()
+                            .rev()
+                            .fold(next, |next, ranges| self.push(State::Class { ranges, next }))
+                    })
+                    .collect();
+                match targets.len() {
+                    1 => targets[0],
+                    _ => self.push(State::Split(targets)),
+                }
+            }
+            HirKind::Anchor(anchor) => match anchor {
+                Anchor::StartText => self.push(State::Look {
+                    look: Look::Start,
+                    next,
+                }),
+                Anchor::EndText => self.push(State::Look {
+                    look: Look::End,
+                    next,
+                }),
+                Anchor::StartLine | Anchor::EndLine => unreachable!(),
+            },
+            HirKind::WordBoundary(_) => unreachable!(),
+            HirKind::Repetition(repetition) => {
+                let (min, max) = repetition_bounds(&repetition.kind);
+                let mut entry = match max {
+                    Some(max) => {
+                        // every optional copy may skip straight to `next`
+                        let mut entry = next;
+                        for _i in min..max {
+                            let copy = self.lower(&repetition.hir, entry);
+                            entry = self.push(State::Split(vec![copy, next]));
+                        }
+                        entry
+                    }
+                    None => {
+                        let head = self.push(State::Split(vec![]));
+                        let body = self.lower(&repetition.hir, head);
+                        self.states[head] = State::Split(vec![body, next]);
+                        head
+                    }
+                };
+                for _i in 0..min {
+                    entry = self.lower(&repetition.hir, entry);
+                }
+                entry
+            }
+            HirKind::Group(group) => self.lower(&group.hir, next),
+            HirKind::Concat(hirs) => hirs
+                .iter()
+                .rev()
+                .fold(next, |next, hir| self.lower(hir, next)),
+            HirKind::Alternation(hirs) => {
+                let targets = hirs.iter().map(|hir| self.lower(hir, next)).collect();
+                self.push(State::Split(targets))
+            }
+        }
+    }
+}
+
+// An alternation being emitted: the branch to patch with the start of the next
+// alternative, and the jumps to patch with the end of the alternation.
+struct AlternationPatch {
+    branch: usize,
+    jumps: Vec<usize>,
+    remaining: usize,
 }
 
 struct ProgramFactory {
     program: Program,
     is_repetition: bool,
-    branch_counter: usize,
-    jump_counter: usize,
+    alternations: Vec<AlternationPatch>,
+    // depth inside a sub-expression that has already been emitted
+    skip_depth: usize,
 }
 
 impl Default for ProgramFactory {
@@ -26,8 +467,214 @@ impl Default for ProgramFactory {
         Self {
             program: Vec::new(),
             is_repetition: false,
-            branch_counter: 0,
-            jump_counter: 0,
+            alternations: Vec::new(),
+            skip_depth: 0,
+        }
+    }
+}
+
+impl ProgramFactory {
+    fn push_branch(&mut self, pc: usize) -> usize {
+        self.program.push(ProgramItem {
+            instruction: Instruction::Branch(pc),
+            action: Action {
+                next: self.program.len() + 1, // unused value
+                offset: 0,                    // unused value
+            },
+        });
+        self.program.len() - 1
+    }
+
+    fn push_jump(&mut self, pc: usize) -> usize {
+        self.program.push(ProgramItem {
+            instruction: Instruction::Jump(pc),
+            action: Action { next: 0, offset: 0 },
+        });
+        self.program.len() - 1
+    }
+
+    fn push_save(&mut self, slot: usize) {
+        self.program.push(ProgramItem {
+            instruction: Instruction::Save(slot),
+            action: Action {
+                next: self.program.len() + 1,
+                offset: 0,
+            },
+        });
+    }
+
+    fn push_interval(&mut self, ranges: Vec<ByteRange>) {
+        self.program.push(ProgramItem {
+            instruction: Instruction::IntervalChar(IntervalCharOptions {
+                range: ranges,
+                can_repeat: false,
+                is_optional: false,
+            }),
+            action: Action {
+                next: self.program.len() + 1,
+                offset: 1,
+            },
+        });
+    }
+
+    // A class is tried one UTF-8 sequence after the other, like an alternation.
+    fn push_class(&mut self, class: &Class) {
+        let sequences = class_sequences(class);
+        if sequences.is_empty() {
+            // nothing can match
+            self.push_interval(vec![]);
+        }
+        let mut jumps = vec![];
+        for (index, sequence) in sequences.iter().enumerate() {
+            let branch = (index + 1 < sequences.len()).then(|| self.push_branch(0));
+            for ranges in sequence.iter() {
+                self.push_interval(ranges.clone());
+            }
+            if let Some(branch) = branch {
+                jumps.push(self.push_jump(0));
+                self.program[branch].instruction = Instruction::Branch(self.program.len());
+            }
+        }
+        for jump in jumps {
+            self.program[jump].instruction = Instruction::Jump(self.program.len());
+        }
+    }
+
+    // Appends a program compiled on its own, moving its targets along.
+    fn push_fragment(&mut self, fragment: &Program) {
+        let base = self.program.len();
+        for item in fragment.iter() {
+            let instruction = match item.instruction.clone() {
+                Instruction::Branch(pc) => Instruction::Branch(pc + base),
+                Instruction::Jump(pc) => Instruction::Jump(pc + base),
+                instruction => instruction,
+            };
+            self.program.push(ProgramItem {
+                instruction,
+                action: Action {
+                    next: item.action.next + base,
+                    offset: item.action.offset,
+                },
+            });
+        }
+    }
+
+    // Emits a node of the trie of prefixes built by `Compiler::compile_set`,
+    // then what follows it one after the other, like an alternation. Nothing
+    // needs to jump over the following ones since each of them ends with an
+    // `Accept`.
+    fn push_prefix_node(&mut self, trie: &[PrefixNode], node: usize, depth: usize, programs: &[Program]) {
+        if let Some(item) = &trie[node].item {
+            self.program.push(ProgramItem {
+                instruction: item.instruction.clone(),
+                action: Action {
+                    next: self.program.len() + 1,
+                    offset: item.action.offset,
+                },
+            });
+        }
+        let count = trie[node].children.len() + trie[node].patterns.len();
+        for index in 0..count {
+            let branch = (index + 1 < count).then(|| self.push_branch(0));
+            match trie[node].children.get(index) {
+                Some(child) => self.push_prefix_node(trie, *child, depth + 1, programs),
+                None => {
+                    let pattern = trie[node].patterns[index - trie[node].children.len()];
+                    let program = &programs[pattern];
+                    // the rest of the program, moved back over its prefix
+                    let suffix: Program = program[depth..]
+                        .iter()
+                        .map(|item| ProgramItem {
+                            instruction: match item.instruction.clone() {
+                                Instruction::Branch(pc) => Instruction::Branch(pc - depth),
+                                Instruction::Jump(pc) => Instruction::Jump(pc - depth),
+                                instruction => instruction,
+                            },
+                            action: Action {
+                                next: item.action.next.saturating_sub(depth),
+                                offset: item.action.offset,
+                            },
+                        })
+                        .collect();
+                    self.push_fragment(&suffix);
+                    self.program.push(ProgramItem {
+                        instruction: Instruction::Accept(pattern),
+                        action: Action {
+                            next: self.program.len() + 1,
+                            offset: 0,
+                        },
+                    });
+                }
+            }
+            if let Some(branch) = branch {
+                self.program[branch].instruction = Instruction::Branch(self.program.len());
+            }
+        }
+    }
+
+    // Repetition of anything else than a single character or class, and lazy
+    // repetitions. The sub-expression is compiled once and copied as many
+    // times as needed, with branches to leave the optional copies and jumps to
+    // loop. A branch goes on with the next instruction first: a greedy
+    // repetition continues there, a lazy one jumps out of the repetition.
+    fn push_repetition(&mut self, repetition: &Repetition) {
+        let fragment = visit(&repetition.hir, ProgramFactory::default()).unwrap();
+        let greedy = repetition.greedy;
+        match repetition_bounds(&repetition.kind) {
+            (0, None) => {
+                let head = self.push_branch(0);
+                let exit = (!greedy).then(|| self.push_jump(0));
+                let body = self.program.len();
+                self.push_fragment(&fragment);
+                self.push_jump(head);
+                let end = self.program.len();
+                match exit {
+                    Some(exit) => {
+                        self.program[head].instruction = Instruction::Branch(body);
+                        self.program[exit].instruction = Instruction::Jump(end);
+                    }
+                    None => self.program[head].instruction = Instruction::Branch(end),
+                }
+            }
+            (min, None) => {
+                for _i in 1..min {
+                    self.push_fragment(&fragment);
+                }
+                let head = self.program.len();
+                self.push_fragment(&fragment);
+                if greedy {
+                    self.push_branch(self.program.len() + 2);
+                    self.push_jump(head);
+                } else {
+                    self.push_branch(self.program.len() + 2);
+                    self.push_jump(self.program.len() + 2);
+                    self.push_jump(head);
+                }
+            }
+            (min, Some(max)) => {
+                for _i in 0..min {
+                    self.push_fragment(&fragment);
+                }
+                let exits: Vec<usize> = (min..max)
+                    .map(|_i| {
+                        let exit = if greedy {
+                            self.push_branch(0)
+                        } else {
+                            self.push_branch(self.program.len() + 2);
+                            self.push_jump(0)
+                        };
+                        self.push_fragment(&fragment);
+                        exit
+                    })
+                    .collect();
+                let end = self.program.len();
+                for exit in exits {
+                    self.program[exit].instruction = match self.program[exit].instruction {
+                        Instruction::Branch(_) => Instruction::Branch(end),
+                        _ => Instruction::Jump(end),
+                    };
+                }
+            }
         }
     }
 }
@@ -37,11 +684,23 @@ impl Visitor for ProgramFactory {
     type Output = Vec<ProgramItem>;
 
     fn visit_post(&mut self, hir: &Hir) -> Result<(), Self::Err> {
+        if self.skip_depth > 0 {
+            self.skip_depth -= 1;
+            return Ok(());
+        }
         if let HirKind::Repetition(_) = hir.kind() {
             self.is_repetition = false;
         }
         if let HirKind::Alternation(_) = hir.kind() {
-            self.program[self.jump_counter].instruction = Instruction::Jump(self.program.len());
+            let alternation = self.alternations.pop().unwrap();
+            for jump in alternation.jumps {
+                self.program[jump].instruction = Instruction::Jump(self.program.len());
+            }
+        }
+        if let HirKind::Group(group) = hir.kind() {
+            if let Some(index) = capture_index(group) {
+                self.push_save(2 * index + 1);
+            }
         }
         Ok(())
     }
@@ -49,41 +708,28 @@ impl Visitor for ProgramFactory {
     fn visit_pre(&mut self, hir: &Hir) -> Result<(), Self::Err> {
         let mut start = 0;
 
+        if self.skip_depth > 0 {
+            self.skip_depth += 1;
+            return Ok(());
+        }
+
         match hir.kind() {
             HirKind::Concat(_) => {}
             HirKind::Literal(literal) => {
                 if !self.is_repetition {
-                    match literal {
-                        Literal::Unicode(c) => {
-                            self.program.push(ProgramItem {
-                                instruction: Instruction::Char(*c as u8),
-                                action: Action {
-                                    next: self.program.len() + 1 + start,
-                                    offset: 1,
-                                },
-                            });
-                        }
-                        Literal::Byte(b) => {
-                            self.program.push(ProgramItem {
-                                instruction: Instruction::Char(*b),
-                                action: Action {
-                                    next: self.program.len() + 1 + start,
-                                    offset: 1,
-                                },
-                            });
-                        }
+                    for byte in literal_bytes(literal) {
+                        self.program.push(ProgramItem {
+                            instruction: Instruction::Char(byte),
+                            action: Action {
+                                next: self.program.len() + 1 + start,
+                                offset: 1,
+                            },
+                        });
                     }
                 }
             }
-            HirKind::Empty => {
-                self.program.push(ProgramItem {
-                    instruction: Instruction::Match,
-                    action: Action {
-                        next: self.program.len() + 1 + start,
-                        offset: 1,
-                    },
-                });
-            }
+            // matches without consuming anything
+            HirKind::Empty => {}
             HirKind::Anchor(anchor) => match anchor {
                 Anchor::StartText => {
                     start = self.program.len();
@@ -104,18 +750,36 @@ impl Visitor for ProgramFactory {
                         },
                     });
                 }
-                Anchor::StartLine => todo!(),
-                Anchor::EndLine => todo!(),
+                Anchor::StartLine => {
+                    self.program.push(ProgramItem {
+                        instruction: Instruction::StartLine,
+                        action: Action {
+                            next: self.program.len() + 1,
+                            offset: 0,
+                        },
+                    });
+                }
+                Anchor::EndLine => {
+                    self.program.push(ProgramItem {
+                        instruction: Instruction::EndLine,
+                        action: Action {
+                            next: self.program.len() + 1,
+                            offset: 0,
+                        },
+                    });
+                }
             },
-            HirKind::Alternation(_) => {
-                self.program.push(ProgramItem {
-                    instruction: Instruction::Branch(0),
-                    action: Action {
-                        next: self.program.len() + 1, // unused value
-                        offset: 0,                    // unused value
-                    },
+            HirKind::Alternation(alternatives) => {
+                let branch = self.push_branch(0);
+                self.alternations.push(AlternationPatch {
+                    branch,
+                    jumps: vec![],
+                    remaining: alternatives.len(),
                 });
-                self.branch_counter = self.program.len() - 1;
+            }
+            HirKind::Repetition(repetition) if !repetition.greedy || !is_single_byte(&repetition.hir) => {
+                self.push_repetition(repetition);
+                self.skip_depth = 1;
             }
             HirKind::Repetition(repetition) => {
                 self.is_repetition = true;
@@ -147,35 +811,32 @@ impl Visitor for ProgramFactory {
                                 },
                             });
                         }
-                        HirKind::Class(class) => match class {
-                            Class::Unicode(set) => {
-                                let range_chars = set.ranges().to_owned();
-                                self.program.push(ProgramItem {
-                                    instruction: Instruction::IntervalChar(IntervalCharOptions {
-                                        range: range_chars.clone(),
-                                        can_repeat: false,
-                                        is_optional: false,
-                                    }),
-                                    action: Action {
-                                        next: self.program.len() + 1 + start,
-                                        offset: 1,
-                                    },
-                                });
-                                self.program.push(ProgramItem {
-                                    instruction: Instruction::IntervalChar(IntervalCharOptions {
-                                        range: range_chars,
-                                        can_repeat: true,
-                                        is_optional: false,
-                                    }),
-                                    action: Action {
-                                        next: self.program.len() + 1 + start,
-                                        offset: 1,
-                                    },
-                                });
-                            }
-                            Class::Bytes(_) => todo!(),
-                        },
-                        _ => {}
+                        HirKind::Class(class) => {
+                            let range_chars = class_ranges(class);
+                            self.program.push(ProgramItem {
+                                instruction: Instruction::IntervalChar(IntervalCharOptions {
+                                    range: range_chars.clone(),
+                                    can_repeat: false,
+                                    is_optional: false,
+                                }),
+                                action: Action {
+                                    next: self.program.len() + 1 + start,
+                                    offset: 1,
+                                },
+                            });
+                            self.program.push(ProgramItem {
+                                instruction: Instruction::IntervalChar(IntervalCharOptions {
+                                    range: range_chars,
+                                    can_repeat: true,
+                                    is_optional: false,
+                                }),
+                                action: Action {
+                                    next: self.program.len() + 1 + start,
+                                    offset: 1,
+                                },
+                            });
+                        }
+                        _ => unreachable!(),
                     },
                     RepetitionKind::ZeroOrMore => match repetition.hir.kind() {
                         HirKind::Literal(literal) => {
@@ -193,24 +854,21 @@ impl Visitor for ProgramFactory {
                                 },
                             });
                         }
-                        HirKind::Class(class) => match class {
-                            Class::Unicode(set) => {
-                                let range_chars = set.ranges().to_owned();
-                                self.program.push(ProgramItem {
-                                    instruction: Instruction::IntervalChar(IntervalCharOptions {
-                                        range: range_chars,
-                                        can_repeat: true,
-                                        is_optional: false,
-                                    }),
-                                    action: Action {
-                                        next: self.program.len() + 1 + start,
-                                        offset: 1,
-                                    },
-                                });
-                            }
-                            Class::Bytes(_) => todo!(),
-                        },
-                        _ => {}
+                        HirKind::Class(class) => {
+                            let range_chars = class_ranges(class);
+                            self.program.push(ProgramItem {
+                                instruction: Instruction::IntervalChar(IntervalCharOptions {
+                                    range: range_chars,
+                                    can_repeat: true,
+                                    is_optional: false,
+                                }),
+                                action: Action {
+                                    next: self.program.len() + 1 + start,
+                                    offset: 1,
+                                },
+                            });
+                        }
+                        _ => unreachable!(),
                     },
                     RepetitionKind::ZeroOrOne => match repetition.hir.kind() {
                         HirKind::Literal(literal) => {
@@ -228,24 +886,21 @@ impl Visitor for ProgramFactory {
                                 },
                             });
                         }
-                        HirKind::Class(class) => match class {
-                            Class::Unicode(set) => {
-                                let range_chars = set.ranges().to_owned();
-                                self.program.push(ProgramItem {
-                                    instruction: Instruction::IntervalChar(IntervalCharOptions {
-                                        range: range_chars,
-                                        can_repeat: false,
-                                        is_optional: true,
-                                    }),
-                                    action: Action {
-                                        next: self.program.len() + 1 + start,
-                                        offset: 1,
-                                    },
-                                });
-                            }
-                            Class::Bytes(_) => todo!(),
-                        },
-                        _ => {}
+                        HirKind::Class(class) => {
+                            let range_chars = class_ranges(class);
+                            self.program.push(ProgramItem {
+                                instruction: Instruction::IntervalChar(IntervalCharOptions {
+                                    range: range_chars,
+                                    can_repeat: false,
+                                    is_optional: true,
+                                }),
+                                action: Action {
+                                    next: self.program.len() + 1 + start,
+                                    offset: 1,
+                                },
+                            });
+                        }
+                        _ => unreachable!(),
                     },
                     RepetitionKind::Range(range) => match range {
                         RepetitionRange::Exactly(n) => match repetition.hir.kind() {
@@ -266,28 +921,25 @@ impl Visitor for ProgramFactory {
                                     });
                                 }
                             }
-                            HirKind::Class(class) => match class {
-                                Class::Unicode(set) => {
-                                    let range_chars = set.ranges().to_owned();
-                                    for _i in 0..n {
-                                        self.program.push(ProgramItem {
-                                            instruction: Instruction::IntervalChar(
-                                                IntervalCharOptions {
-                                                    range: range_chars.clone(),
-                                                    can_repeat: false,
-                                                    is_optional: false,
-                                                },
-                                            ),
-                                            action: Action {
-                                                next: self.program.len() + 1 + start,
-                                                offset: 1,
+                            HirKind::Class(class) => {
+                                let range_chars = class_ranges(class);
+                                for _i in 0..n {
+                                    self.program.push(ProgramItem {
+                                        instruction: Instruction::IntervalChar(
+                                            IntervalCharOptions {
+                                                range: range_chars.clone(),
+                                                can_repeat: false,
+                                                is_optional: false,
                                             },
-                                        });
-                                    }
+                                        ),
+                                        action: Action {
+                                            next: self.program.len() + 1 + start,
+                                            offset: 1,
+                                        },
+                                    });
                                 }
-                                Class::Bytes(_) => todo!(),
-                            },
-                            _ => {}
+                            }
+                            _ => unreachable!(),
                         },
                         RepetitionRange::AtLeast(n) => match repetition.hir.kind() {
                             HirKind::Literal(literal) => {
@@ -318,29 +970,14 @@ impl Visitor for ProgramFactory {
                                     },
                                 });
                             }
-                            HirKind::Class(class) => match class {
-                                Class::Unicode(set) => {
-                                    let range_chars = set.ranges().to_owned();
-                                    for _i in 0..n {
-                                        self.program.push(ProgramItem {
-                                            instruction: Instruction::IntervalChar(
-                                                IntervalCharOptions {
-                                                    range: range_chars.clone(),
-                                                    can_repeat: false,
-                                                    is_optional: false,
-                                                },
-                                            ),
-                                            action: Action {
-                                                next: self.program.len() + 1 + start,
-                                                offset: 1,
-                                            },
-                                        });
-                                    }
+                            HirKind::Class(class) => {
+                                let range_chars = class_ranges(class);
+                                for _i in 0..n {
                                     self.program.push(ProgramItem {
                                         instruction: Instruction::IntervalChar(
                                             IntervalCharOptions {
-                                                range: range_chars,
-                                                can_repeat: true,
+                                                range: range_chars.clone(),
+                                                can_repeat: false,
                                                 is_optional: false,
                                             },
                                         ),
@@ -350,9 +987,21 @@ impl Visitor for ProgramFactory {
                                         },
                                     });
                                 }
-                                Class::Bytes(_) => todo!(),
-                            },
-                            _ => {}
+                                self.program.push(ProgramItem {
+                                    instruction: Instruction::IntervalChar(
+                                        IntervalCharOptions {
+                                            range: range_chars,
+                                            can_repeat: true,
+                                            is_optional: false,
+                                        },
+                                    ),
+                                    action: Action {
+                                        next: self.program.len() + 1 + start,
+                                        offset: 1,
+                                    },
+                                });
+                            }
+                            _ => unreachable!(),
                         },
                         RepetitionRange::Bounded(m, n) => match repetition.hir.kind() {
                             HirKind::Literal(literal) => {
@@ -386,82 +1035,93 @@ impl Visitor for ProgramFactory {
                                     });
                                 }
                             }
-                            HirKind::Class(class) => match class {
-                                Class::Unicode(set) => {
-                                    let range_chars = set.ranges().to_owned();
-                                    for _i in 0..m {
-                                        self.program.push(ProgramItem {
-                                            instruction: Instruction::IntervalChar(
-                                                IntervalCharOptions {
-                                                    range: range_chars.clone(),
-                                                    can_repeat: false,
-                                                    is_optional: false,
-                                                },
-                                            ),
-                                            action: Action {
-                                                next: self.program.len() + 1 + start,
-                                                offset: 1,
+                            HirKind::Class(class) => {
+                                let range_chars = class_ranges(class);
+                                for _i in 0..m {
+                                    self.program.push(ProgramItem {
+                                        instruction: Instruction::IntervalChar(
+                                            IntervalCharOptions {
+                                                range: range_chars.clone(),
+                                                can_repeat: false,
+                                                is_optional: false,
                                             },
-                                        });
-                                    }
-                                    for _i in 0..n - m {
-                                        self.program.push(ProgramItem {
-                                            instruction: Instruction::IntervalChar(
-                                                IntervalCharOptions {
-                                                    range: range_chars.clone(),
-                                                    can_repeat: false,
-                                                    is_optional: true,
-                                                },
-                                            ),
-                                            action: Action {
-                                                next: self.program.len() + 1 + start,
-                                                offset: 1,
+                                        ),
+                                        action: Action {
+                                            next: self.program.len() + 1 + start,
+                                            offset: 1,
+                                        },
+                                    });
+                                }
+                                for _i in 0..n - m {
+                                    self.program.push(ProgramItem {
+                                        instruction: Instruction::IntervalChar(
+                                            IntervalCharOptions {
+                                                range: range_chars.clone(),
+                                                can_repeat: false,
+                                                is_optional: true,
                                             },
-                                        });
-                                    }
+                                        ),
+                                        action: Action {
+                                            next: self.program.len() + 1 + start,
+                                            offset: 1,
+                                        },
+                                    });
                                 }
-                                Class::Bytes(_) => todo!(),
-                            },
-                            _ => {}
+                            }
+                            _ => unreachable!(),
                         },
                     },
                 }
             }
             HirKind::Class(class) => {
                 if !self.is_repetition {
-                    match class {
-                        Class::Unicode(set) => {
-                            let range_chars = set.ranges().to_owned();
-                            self.program.push(ProgramItem {
-                                instruction: Instruction::IntervalChar(IntervalCharOptions {
-                                    range: range_chars,
-                                    can_repeat: false,
-                                    is_optional: false,
-                                }),
-                                action: Action {
-                                    next: self.program.len() + 1 + start,
-                                    offset: 1,
-                                },
-                            });
-                        }
-                        Class::Bytes(_) => todo!(),
-                    }
+                    self.push_class(class);
+                }
+            }
+            HirKind::Group(group) => {
+                if let Some(index) = capture_index(group) {
+                    self.push_save(2 * index);
                 }
             }
-            HirKind::Group(_) => {}
-            HirKind::WordBoundary(_) => {}
+            HirKind::WordBoundary(word_boundary) => {
+                // the instructions only know the ASCII word bytes, see
+                // `program::is_word_byte`: a Unicode `\b` takes the bytes of
+                // non-ASCII characters for non-word bytes, so `\bcaf\b`
+                // matches in "café" unlike with the regex crate
+                let instruction = match word_boundary {
+                    WordBoundary::Unicode | WordBoundary::Ascii => Instruction::WordBoundary,
+                    WordBoundary::UnicodeNegate | WordBoundary::AsciiNegate => {
+                        Instruction::NotWordBoundary
+                    }
+                };
+                self.program.push(ProgramItem {
+                    instruction,
+                    action: Action {
+                        next: self.program.len() + 1,
+                        offset: 0,
+                    },
+                });
+            }
         }
         Ok(())
     }
 
     fn visit_alternation_in(&mut self) -> Result<(), Self::Err> {
-        self.program.push(ProgramItem {
-            instruction: Instruction::Jump(0),
-            action: Action { next: 0, offset: 0 },
-        });
-        self.jump_counter = self.program.len() - 1;
+        if self.skip_depth > 0 {
+            return Ok(());
+        }
+        let jump = self.push_jump(0);
+        let pc = self.program.len();
+        let alternation = self.alternations.last_mut().unwrap();
+        alternation.jumps.push(jump);
+        self.program[alternation.branch].instruction = Instruction::Branch(pc);
 
-        self.program[self.branch_counter].instruction = Instruction::Branch(self.program.len());
+        // the last alternative is the fallback of the previous branch
+        alternation.remaining -= 1;
+        if alternation.remaining > 1 {
+            let branch = self.push_branch(0);
+            self.alternations.last_mut().unwrap().branch = branch;
+        }
         Ok(())
     }
     fn finish(self) -> Result<Self::Output, Self::Err> {
@@ -0,0 +1,266 @@
+use std::collections::{BTreeSet, HashMap};
+
+use tfhe_regex::EncodedCipherTrait;
+
+use crate::nfa::{ByteRange, Nfa, State};
+use crate::program::CiphertextRange;
+
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    // Bytes that no state of the automaton can tell apart share the same class.
+    pub classes: Vec<Vec<ByteRange>>,
+    // Next state for every state and every byte class.
+    pub transitions: Vec<Vec<usize>>,
+    // Whether the input matches when it ends in this state.
+    pub accepting: Vec<bool>,
+    pub start: usize,
+}
+
+#[derive(Clone)]
+pub struct CipherDfa<T: EncodedCipherTrait + Clone> {
+    pub classes: Vec<Vec<CiphertextRange<T>>>,
+    pub transitions: Vec<Vec<usize>>,
+    pub accepting: Vec<bool>,
+    pub start: usize,
+}
+
+// A state of the subset construction. Once a match has been found the search is
+// over, so every set of NFA states that reaches `Match` collapses into `Matched`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subset {
+    Active {
+        targets: BTreeSet<usize>,
+        at_start: bool,
+    },
+    Matched,
+}
+
+impl Dfa {
+    /// Determinizes the NFA with the subset construction and minimizes the
+    /// result. The DFA searches for the pattern anywhere in the input, like the
+    /// NFA machines do.
+    pub fn new(nfa: &Nfa) -> Self {
+        let alphabet = byte_classes(nfa);
+        let (transitions, accepting) = determinize(nfa, &alphabet);
+        minimize(&alphabet, &transitions, &accepting)
+    }
+
+    pub fn class_of(&self, byte: u8) -> usize {
+        self.classes
+            .iter()
+            .position(|ranges| {
+                ranges
+                    .iter()
+                    .any(|range| range.start <= byte && byte <= range.end)
+            })
+            .unwrap()
+    }
+
+    pub fn is_dead(&self, state: usize) -> bool {
+        is_dead(&self.transitions, &self.accepting, state)
+    }
+}
+
+impl<T: EncodedCipherTrait + Clone> CipherDfa<T> {
+    pub fn is_dead(&self, state: usize) -> bool {
+        is_dead(&self.transitions, &self.accepting, state)
+    }
+}
+
+// A state that can never lead to a match, whatever the rest of the input is.
+fn is_dead(transitions: &[Vec<usize>], accepting: &[bool], state: usize) -> bool {
+    !accepting[state] && transitions[state].iter().all(|next| *next == state)
+}
+
+// Splits the bytes into the intervals on which every character test of the NFA
+// gives the same answer.
+fn byte_classes(nfa: &Nfa) -> Vec<ByteRange> {
+    let mut boundaries = vec![false; 257];
+    boundaries[0] = true;
+    boundaries[256] = true;
+    for state in nfa.states.iter() {
+        match state {
+            State::Char { byte, .. } => {
+                boundaries[*byte as usize] = true;
+                boundaries[*byte as usize + 1] = true;
+            }
+            State::Class { ranges, .. } => {
+                for range in ranges.iter() {
+                    boundaries[range.start as usize] = true;
+                    boundaries[range.end as usize + 1] = true;
+                }
+            }
+            State::Split(_) | State::Look { .. } | State::Match => {}
+        }
+    }
+    let starts: Vec<usize> = (0..=256).filter(|index| boundaries[*index]).collect();
+    starts
+        .windows(2)
+        .map(|bounds| ByteRange {
+            start: bounds[0] as u8,
+            end: (bounds[1] - 1) as u8,
+        })
+        .collect()
+}
+
+fn reaches_match(nfa: &Nfa, targets: &BTreeSet<usize>, at_start: bool, at_end: bool) -> bool {
+    targets
+        .iter()
+        .chain(std::iter::once(&nfa.start))
+        .flat_map(|target| nfa.closure(*target, at_start, at_end))
+        .any(|state| matches!(nfa.states[*state], State::Match))
+}
+
+fn determinize(nfa: &Nfa, alphabet: &[ByteRange]) -> (Vec<Vec<usize>>, Vec<bool>) {
+    let mut subsets: Vec<Subset> = vec![];
+    let mut indexes: HashMap<Subset, usize> = HashMap::new();
+    let mut transitions: Vec<Vec<usize>> = vec![];
+    let mut accepting: Vec<bool> = vec![];
+
+    let normalize = |targets: BTreeSet<usize>, at_start: bool| {
+        if reaches_match(nfa, &targets, at_start, false) {
+            Subset::Matched
+        } else {
+            Subset::Active { targets, at_start }
+        }
+    };
+
+    let start = normalize(BTreeSet::new(), true);
+    indexes.insert(start.clone(), 0);
+    subsets.push(start);
+
+    let mut current = 0;
+    while current < subsets.len() {
+        let (next_subsets, is_accepting) = match &subsets[current] {
+            Subset::Matched => (vec![Subset::Matched; alphabet.len()], true),
+            Subset::Active { targets, at_start } => {
+                let active: BTreeSet<usize> = targets
+                    .iter()
+                    .chain(std::iter::once(&nfa.start))
+                    .flat_map(|target| nfa.closure(*target, *at_start, false))
+                    .copied()
+                    .collect();
+                let next_subsets = alphabet
+                    .iter()
+                    .map(|class| {
+                        let byte = class.start;
+                        let next_targets = active
+                            .iter()
+                            .filter_map(|state| match &nfa.states[*state] {
+                                State::Char { byte: c, next } if *c == byte => Some(*next),
+                                State::Class { ranges, next }
+                                    if ranges
+                                        .iter()
+                                        .any(|range| range.start <= byte && byte <= range.end) =>
+                                {
+                                    Some(*next)
+                                }
+                                _ => None,
+                            })
+                            .collect();
+                        normalize(next_targets, false)
+                    })
+                    .collect();
+                (next_subsets, reaches_match(nfa, targets, *at_start, true))
+            }
+        };
+
+        let mut row = vec![];
+        for subset in next_subsets {
+            let index = match indexes.get(&subset) {
+                Some(index) => *index,
+                None => {
+                    indexes.insert(subset.clone(), subsets.len());
+                    subsets.push(subset);
+                    subsets.len() - 1
+                }
+            };
+            row.push(index);
+        }
+        transitions.push(row);
+        accepting.push(is_accepting);
+        current += 1;
+    }
+    (transitions, accepting)
+}
+
+// Moore's partition refinement: states stay in the same block as long as they
+// agree on acceptance and on the blocks they move to for every byte class.
+fn minimize(alphabet: &[ByteRange], transitions: &[Vec<usize>], accepting: &[bool]) -> Dfa {
+    let mut blocks: Vec<usize> = accepting.iter().map(|is_accepting| *is_accepting as usize).collect();
+    let mut block_count = 0;
+    loop {
+        let mut indexes: HashMap<(usize, Vec<usize>), usize> = HashMap::new();
+        let refined: Vec<usize> = (0..transitions.len())
+            .map(|state| {
+                let signature = (
+                    blocks[state],
+                    transitions[state].iter().map(|next| blocks[*next]).collect(),
+                );
+                let index = indexes.len();
+                *indexes.entry(signature).or_insert(index)
+            })
+            .collect();
+        let refined_count = indexes.len();
+        blocks = refined;
+        if refined_count == block_count {
+            break;
+        }
+        block_count = refined_count;
+    }
+
+    let mut minimized_transitions = vec![vec![]; block_count];
+    let mut minimized_accepting = vec![false; block_count];
+    for state in 0..transitions.len() {
+        minimized_transitions[blocks[state]] = transitions[state]
+            .iter()
+            .map(|next| blocks[*next])
+            .collect();
+        minimized_accepting[blocks[state]] = accepting[state];
+    }
+
+    // byte classes that every state handles the same way can be merged
+    let mut classes: Vec<Vec<ByteRange>> = vec![];
+    let mut columns: Vec<Vec<usize>> = vec![];
+    for (class, range) in alphabet.iter().enumerate() {
+        let column: Vec<usize> = minimized_transitions.iter().map(|row| row[class]).collect();
+        match columns.iter().position(|existing| *existing == column) {
+            Some(index) => classes[index].push(*range),
+            None => {
+                classes.push(vec![*range]);
+                columns.push(column);
+            }
+        }
+    }
+    let transitions = (0..block_count)
+        .map(|state| columns.iter().map(|column| column[state]).collect())
+        .collect();
+
+    Dfa {
+        classes,
+        transitions,
+        accepting: minimized_accepting,
+        start: blocks[0],
+    }
+}
+
+pub fn cipher_dfa<T: EncodedCipherTrait + Clone>(client_key: &T::ClientKey, dfa: Dfa) -> CipherDfa<T> {
+    CipherDfa {
+        classes: dfa
+            .classes
+            .iter()
+            .map(|ranges| {
+                ranges
+                    .iter()
+                    .map(|range| CiphertextRange {
+                        start: T::encrypt(client_key, range.start),
+                        end: T::encrypt(client_key, range.end),
+                    })
+                    .collect()
+            })
+            .collect(),
+        transitions: dfa.transitions,
+        accepting: dfa.accepting,
+        start: dfa.start,
+    }
+}
@@ -1,6 +1,13 @@
 use crate::CheckerCipher;
-use tfhe::shortint::{parameters::PARAM_MESSAGE_4_CARRY_4, prelude::*};
-use tfhe_regex::{EncodedCipher2bits, EncodedCipher4bits, EncodedCipherTrait};
+use tfhe::shortint::{
+    parameters::{PARAM_MESSAGE_4_CARRY_4, PARAM_MESSAGE_8_CARRY_0},
+    prelude::*,
+};
+use tfhe_regex::{
+    ct_compare_limbs, ct_limbs_order, ct_limbs_scalar_order, gen_bits_keys, limbs_in_table_bootstraps,
+    EncodedCipher2bits, EncodedCipher4bits, EncodedCipher8bits, EncodedCipherBits, EncodedCipherClass,
+    EncodedCipherOneHot, EncodedCipherTrait, SelectCipherTrait,
+};
 
 type TestEncodedCipher = EncodedCipher2bits;
 
@@ -91,3 +98,460 @@ fn check_less_or_equal_fail() {
         assert!(!ct_is_true(&result, &client_key))
     }
 }
+
+#[test]
+fn check_select() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (value, condition, replacement, expected) in [
+        (230_u8, 1_u64, 42_u8, 42_u8),
+        (230_u8, 0_u64, 42_u8, 230_u8),
+        (0_u8, 1_u64, 255_u8, 255_u8),
+        (255_u8, 1_u64, 0_u8, 0_u8),
+    ] {
+        let cipher = TestEncodedCipher::encrypt(&client_key, value);
+        let condition = client_key.encrypt(condition);
+        let result = cipher.select(&server_key, &condition, replacement);
+        assert_eq!(result.decrypt(&client_key), expected);
+    }
+}
+
+#[test]
+fn check_scalar_comparisons() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for left in [0_u8, 1, 7, 63, 64, 128, 200, 255] {
+        for right in [0_u8, 1, 7, 63, 64, 128, 200, 255] {
+            let cipher = TestEncodedCipher::encrypt(&client_key, left);
+            let equal = cipher.clone().scalar_equal(&server_key, right);
+            let greater_or_equal = cipher.clone().scalar_greater_or_equal(&server_key, right);
+            let less_or_equal = cipher.scalar_less_or_equal(&server_key, right);
+            assert_eq!(ct_is_true(&equal, &client_key), left == right);
+            assert_eq!(ct_is_true(&greater_or_equal, &client_key), left >= right);
+            assert_eq!(ct_is_true(&less_or_equal, &client_key), left <= right);
+        }
+    }
+}
+
+fn class(ranges: &[(u8, u8)]) -> [bool; 256] {
+    let mut table = [false; 256];
+    for (start, end) in ranges {
+        for byte in *start..=*end {
+            table[byte as usize] = true;
+        }
+    }
+    table
+}
+
+#[test]
+fn check_in_table() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for table in [
+        class(&[(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')]),
+        class(&[(0x80, 0xff)]),
+        class(&[(b'\n', b'\n')]),
+        class(&[(0, 255)]),
+        class(&[]),
+    ] {
+        for byte in 0..=255_u8 {
+            let cipher = TestEncodedCipher::encrypt(&client_key, byte);
+            let result = cipher.in_table(&server_key, &table);
+            assert_eq!(client_key.decrypt(&result), table[byte as usize] as u64, "{}", byte);
+        }
+    }
+}
+
+#[test]
+fn check_in_table_bootstraps() {
+    let word = class(&[(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')]);
+    // both limbs of a byte fit in the message and carry space: one lookup
+    assert_eq!(limbs_in_table_bootstraps(&PARAM_MESSAGE_4_CARRY_4, 2, 4, &word), 1);
+    assert_eq!(limbs_in_table_bootstraps(&PARAM_MESSAGE_4_CARRY_4, 2, 4, &class(&[(b'\n', b'\n')])), 1);
+    // the upper nibbles fall into 4 groups, each costing a lookup on both
+    // halves and their product
+    assert_eq!(limbs_in_table_bootstraps(&PARAM_MESSAGE_2_CARRY_2, 4, 2, &word), 12);
+    assert_eq!(limbs_in_table_bootstraps(&PARAM_MESSAGE_2_CARRY_2, 4, 2, &class(&[(0x80, 0xff)])), 1);
+    assert_eq!(limbs_in_table_bootstraps(&PARAM_MESSAGE_2_CARRY_2, 4, 2, &class(&[])), 0);
+}
+
+#[test]
+fn check_8bits_comparisons() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_8_CARRY_0);
+    let values = [0_u8, 1, 2, 3, 126, 127, 128, 129, 200, 201, 254, 255];
+    for left in values {
+        for right in values {
+            let cipher = EncodedCipher8bits::encrypt(&client_key, left);
+            let other = || EncodedCipher8bits::encrypt(&client_key, right);
+            let equal = cipher.clone().equal(&server_key, other());
+            let greater_or_equal = cipher.clone().greater_or_equal(&server_key, other());
+            let less_or_equal = cipher.clone().less_or_equal(&server_key, other());
+            assert_eq!(ct_is_true(&equal, &client_key), left == right, "{} == {}", left, right);
+            assert_eq!(ct_is_true(&greater_or_equal, &client_key), left >= right, "{} >= {}", left, right);
+            assert_eq!(ct_is_true(&less_or_equal, &client_key), left <= right, "{} <= {}", left, right);
+
+            let equal = cipher.clone().scalar_equal(&server_key, right);
+            let greater_or_equal = cipher.clone().scalar_greater_or_equal(&server_key, right);
+            let less_or_equal = cipher.scalar_less_or_equal(&server_key, right);
+            assert_eq!(ct_is_true(&equal, &client_key), left == right);
+            assert_eq!(ct_is_true(&greater_or_equal, &client_key), left >= right);
+            assert_eq!(ct_is_true(&less_or_equal, &client_key), left <= right);
+        }
+    }
+}
+
+#[test]
+fn check_8bits_select_and_in_table() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_8_CARRY_0);
+    let mut table = [false; 256];
+    table[b'a' as usize..=b'z' as usize].fill(true);
+    table[b'_' as usize] = true;
+    for byte in 0..=255_u8 {
+        let cipher = EncodedCipher8bits::encrypt(&client_key, byte);
+        let result = cipher.clone().in_table(&server_key, &table);
+        assert_eq!(client_key.decrypt(&result), table[byte as usize] as u64, "{}", byte);
+
+        for (condition, expected) in [(0_u64, byte), (1_u64, !byte)] {
+            let condition = client_key.encrypt(condition);
+            let result = cipher.clone().select(&server_key, &condition, !byte);
+            assert_eq!(result.decrypt(&client_key), expected);
+        }
+    }
+}
+
+#[test]
+fn check_bits_comparisons() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_2_CARRY_2);
+    let values = [0_u8, 1, 127, 128, 129, 200, 254, 255];
+    for left in values {
+        let cipher = EncodedCipherBits::encrypt(&client_key, left);
+        assert_eq!(cipher.clone().decrypt(&client_key), left);
+        for right in values {
+            let other = EncodedCipherBits::encrypt(&client_key, right);
+            for (result, expected) in [
+                (cipher.clone().equal(&server_key, other.clone()), left == right),
+                (cipher.clone().greater_or_equal(&server_key, other.clone()), left >= right),
+                (cipher.clone().less_or_equal(&server_key, other.clone()), left <= right),
+                (cipher.clone().scalar_equal(&server_key, right), left == right),
+                (cipher.clone().scalar_greater_or_equal(&server_key, right), left >= right),
+                (cipher.clone().scalar_less_or_equal(&server_key, right), left <= right),
+            ] {
+                assert_eq!(ct_is_true(&result, &client_key.shortint), expected, "{} and {}", left, right);
+            }
+        }
+    }
+}
+
+#[test]
+fn check_bits_select_and_in_table() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_2_CARRY_2);
+    let word = class(&[(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')]);
+    for value in [0_u8, b'0', b'9', b'@', b'_', b'`', b'z', 255] {
+        let cipher = EncodedCipherBits::encrypt(&client_key, value);
+        let in_word = cipher.clone().in_table(&server_key, &word);
+        assert_eq!(ct_is_true(&in_word, &client_key.shortint), word[value as usize], "{}", value);
+        for condition in [0, 1] {
+            let ct_condition = client_key.shortint.encrypt(condition);
+            let selected = cipher.clone().select(&server_key, &ct_condition, b'*');
+            let expected = if condition == 1 { b'*' } else { value };
+            assert_eq!(selected.decrypt(&client_key), expected);
+        }
+    }
+}
+
+#[test]
+fn check_one_hot_comparisons() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let values = [0_u8, 1, 7, 63, 64, 128, 200, 255];
+    for left in values {
+        let cipher = EncodedCipherOneHot::encrypt(&client_key, left);
+        assert_eq!(cipher.clone().decrypt(&client_key), left);
+        for right in values {
+            let other = || EncodedCipherOneHot::encrypt(&client_key, right);
+            for (result, expected) in [
+                (cipher.clone().equal(&server_key, other()), left == right),
+                (cipher.clone().greater_or_equal(&server_key, other()), left >= right),
+                (cipher.clone().less_or_equal(&server_key, other()), left <= right),
+                (cipher.clone().scalar_equal(&server_key, right), left == right),
+                (cipher.clone().scalar_greater_or_equal(&server_key, right), left >= right),
+                (cipher.clone().scalar_less_or_equal(&server_key, right), left <= right),
+            ] {
+                assert_eq!(ct_is_true(&result, &client_key), expected, "{} and {}", left, right);
+            }
+        }
+    }
+}
+
+#[test]
+fn check_one_hot_in_range() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let alphabet: Vec<u8> = (b' '..=b'~').collect();
+    for (start, end) in [(b'a', b'z'), (b'0', b'0'), (b'z', b'a'), (0_u8, 255_u8)] {
+        let start = || EncodedCipherOneHot::encrypt(&client_key, start);
+        let end = || EncodedCipherOneHot::encrypt(&client_key, end);
+        for byte in [b' ', b'0', b'1', b'a', b'm', b'z', b'~'] {
+            let expected = start().decrypt(&client_key) <= byte && byte <= end().decrypt(&client_key);
+            let cipher = EncodedCipherOneHot::encrypt_over(&client_key, byte, &alphabet);
+            let result = cipher.in_range(&server_key, start(), end());
+            assert_eq!(ct_is_true(&result, &client_key), expected, "{}", byte);
+        }
+    }
+}
+
+#[test]
+fn check_one_hot_reduced_alphabet() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let alphabet = b"abc.@";
+    let cipher = EncodedCipherOneHot::encrypt_over(&client_key, b'c', alphabet);
+    assert_eq!(cipher.clone().decrypt(&client_key), b'c');
+    assert!(ct_is_true(&cipher.clone().scalar_equal(&server_key, b'c'), &client_key));
+    assert!(!ct_is_true(&cipher.clone().scalar_equal(&server_key, b'a'), &client_key));
+    assert!(ct_is_true(&cipher.clone().scalar_greater_or_equal(&server_key, b'b'), &client_key));
+
+    let other = EncodedCipherOneHot::encrypt(&client_key, b'c');
+    assert!(ct_is_true(&cipher.clone().equal(&server_key, other), &client_key));
+
+    let condition = client_key.encrypt(1);
+    let replaced = cipher.clone().select(&server_key, &condition, b'@');
+    assert_eq!(replaced.decrypt(&client_key), b'@');
+
+    // bytes out of the alphabet have no indicator set
+    let outside = EncodedCipherOneHot::encrypt_over(&client_key, b'z', alphabet);
+    assert_eq!(outside.clone().decrypt(&client_key), 0);
+    assert!(!ct_is_true(&outside.in_table(&server_key, &[true; 256]), &client_key));
+}
+
+// Every comparison of the two encrypted bytes, checking that the results are
+// exactly 0 or 1, carries included. `decryption_key` is the shortint key of the
+// results.
+fn check_pair_order<T>(
+    server_key: &T::ServerKey,
+    decryption_key: &ClientKey,
+    (left, cipher): &(u8, T),
+    (right, other): &(u8, T),
+) where
+    T: EncodedCipherTrait + Clone,
+{
+    let (left, right) = (*left, *right);
+    for (name, result, expected) in [
+        ("==", cipher.clone().equal(server_key, other.clone()), left == right),
+        (">=", cipher.clone().greater_or_equal(server_key, other.clone()), left >= right),
+        ("<=", cipher.clone().less_or_equal(server_key, other.clone()), left <= right),
+        ("scalar ==", cipher.clone().scalar_equal(server_key, right), left == right),
+        ("scalar >=", cipher.clone().scalar_greater_or_equal(server_key, right), left >= right),
+        ("scalar <=", cipher.clone().scalar_less_or_equal(server_key, right), left <= right),
+    ] {
+        let value = decryption_key.decrypt_message_and_carry(&result);
+        assert_eq!(value, expected as u64, "{} {} {}", left, name, right);
+    }
+}
+
+// `check_pair_order` on every pair of the encrypted bytes
+fn check_order<T>(server_key: &T::ServerKey, decryption_key: &ClientKey, ciphers: &[(u8, T)])
+where
+    T: EncodedCipherTrait + Clone,
+{
+    for left in ciphers.iter() {
+        for right in ciphers.iter() {
+            check_pair_order(server_key, decryption_key, left, right);
+        }
+    }
+}
+
+fn check_exhaustive_order<T>(parameters: Parameters)
+where
+    T: EncodedCipherTrait<ClientKey = ClientKey, ServerKey = ServerKey> + Clone,
+{
+    let (client_key, server_key) = gen_keys(parameters);
+    let ciphers: Vec<(u8, T)> = (0..=255).map(|byte| (byte, T::encrypt(&client_key, byte))).collect();
+    check_order(&server_key, &client_key, &ciphers);
+}
+
+// The 16 bytes made of these nibbles, checked pairwise by default: they hold
+// the ends of the byte range, 0x0f and 0x10 on both sides of a nibble, and
+// pairs of bytes that first differ on each limb of 2 or 4 bits.
+const ORDER_NIBBLES: [u8; 4] = [0x0, 0x1, 0x8, 0xf];
+
+fn order_bytes() -> Vec<u8> {
+    ORDER_NIBBLES
+        .iter()
+        .flat_map(|high| ORDER_NIBBLES.iter().map(move |low| high << 4 | low))
+        .collect()
+}
+
+fn check_reduced_order<T>(parameters: Parameters)
+where
+    T: EncodedCipherTrait<ClientKey = ClientKey, ServerKey = ServerKey> + Clone,
+{
+    let (client_key, server_key) = gen_keys(parameters);
+    let ciphers: Vec<(u8, T)> = order_bytes().into_iter().map(|byte| (byte, T::encrypt(&client_key, byte))).collect();
+    check_order(&server_key, &client_key, &ciphers);
+}
+
+#[test]
+fn check_reduced_order_2bits() {
+    check_reduced_order::<EncodedCipher2bits>(PARAM_MESSAGE_2_CARRY_2);
+}
+
+#[test]
+fn check_reduced_order_4bits() {
+    check_reduced_order::<EncodedCipher4bits>(PARAM_MESSAGE_4_CARRY_4);
+}
+
+#[test]
+fn check_reduced_order_8bits() {
+    check_reduced_order::<EncodedCipher8bits>(PARAM_MESSAGE_8_CARRY_0);
+}
+
+#[test]
+fn check_reduced_order_one_hot() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_2);
+    let alphabet = order_bytes();
+    let ciphers: Vec<(u8, EncodedCipherOneHot)> = alphabet
+        .iter()
+        .map(|&byte| (byte, EncodedCipherOneHot::encrypt_over(&client_key, byte, &alphabet)))
+        .collect();
+    check_order(&server_key, &client_key, &ciphers);
+}
+
+// every class ID of a 4 bits message space, the smallest with 16 IDs
+#[test]
+fn check_reduced_order_class() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_4_CARRY_4);
+    let ciphers: Vec<(u8, EncodedCipherClass)> =
+        (0..16).map(|class| (class, EncodedCipherClass::encrypt(&client_key, class))).collect();
+    check_order(&server_key, &client_key, &ciphers);
+}
+
+// every class ID of a 2 bits message space
+#[test]
+fn check_exhaustive_order_class() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_2);
+    let ciphers: Vec<(u8, EncodedCipherClass)> =
+        (0..4).map(|class| (class, EncodedCipherClass::encrypt(&client_key, class))).collect();
+    check_order(&server_key, &client_key, &ciphers);
+}
+
+// The one-hot comparisons only depend on the positions of the bytes in the
+// alphabet, so every pair of bytes of an alphabet covers every alphabet of its
+// length. The lengths go past two refreshes of the sums, every 4 indicators
+// with a 2 bits message and carry space.
+#[test]
+fn check_exhaustive_order_one_hot() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_2);
+    for length in 1..=9 {
+        let alphabet = &b"abcdefghi"[..length];
+        let ciphers: Vec<(u8, EncodedCipherOneHot)> = alphabet
+            .iter()
+            .map(|&byte| (byte, EncodedCipherOneHot::encrypt_over(&client_key, byte, alphabet)))
+            .collect();
+        check_order(&server_key, &client_key, &ciphers);
+    }
+}
+
+// Pairs of bytes with every pair of bits at each position, the bits above
+// equal and the bits below equal or deciding either way: the cases the ripple
+// comparators of `EncodedCipherBits` go through at every bit.
+fn bit_pairs() -> Vec<(u8, u8)> {
+    let mut pairs = Vec::new();
+    for position in 0..8 {
+        let high = ((0xa5_u16 >> (position + 1)) << (position + 1)) as u8;
+        let lows: &[(u8, u8)] = match position {
+            0 => &[(0, 0)],
+            _ => &[(0, 0), (1, 0), (0, 1)],
+        };
+        for (left, right) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            for (low_left, low_right) in lows {
+                pairs.push((high | left << position | low_left, high | right << position | low_right));
+            }
+        }
+    }
+    pairs
+}
+
+#[test]
+fn check_bit_pairs_order_bits() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_2_CARRY_2);
+    for (left, right) in bit_pairs() {
+        let left = (left, EncodedCipherBits::encrypt(&client_key, left));
+        let right = (right, EncodedCipherBits::encrypt(&client_key, right));
+        check_pair_order(&server_key, &client_key.shortint, &left, &right);
+    }
+}
+
+// Every pair of numbers of two 2 bits limbs, the 4^4 pairs of limbs at both
+// positions with the other one deciding either way or equal: comparing more
+// limbs chains these cases, see `check_exhaustive_compare_limbs`.
+#[test]
+fn check_exhaustive_limbs_order() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_2);
+    let numbers: Vec<[Ciphertext; 2]> =
+        (0..16_u64).map(|value| [client_key.encrypt(value >> 2), client_key.encrypt(value & 3)]).collect();
+    for (left, lhs) in numbers.iter().enumerate() {
+        for (right, rhs) in numbers.iter().enumerate() {
+            let (lhs, rhs) = ([&lhs[0], &lhs[1]], [&rhs[0], &rhs[1]]);
+            let rhs_limbs = [(right >> 2) as u8, (right & 3) as u8];
+            for (name, result, expected) in [
+                (">=", ct_limbs_order(&server_key, &lhs, &rhs, true), left >= right),
+                ("<=", ct_limbs_order(&server_key, &lhs, &rhs, false), left <= right),
+                ("scalar >=", ct_limbs_scalar_order(&server_key, &lhs, &rhs_limbs, true), left >= right),
+                ("scalar <=", ct_limbs_scalar_order(&server_key, &lhs, &rhs_limbs, false), left <= right),
+            ] {
+                let value = client_key.decrypt_message_and_carry(&result);
+                assert_eq!(value, expected as u64, "{} {} {}", left, name, right);
+            }
+        }
+    }
+}
+
+// Every chain of the codes of the three limbs above the lowest, as many as
+// `EncodedCipher2bits` has, with both results of the lowest limbs: the first
+// code that isn't 1 decides, and the lowest limbs when there is none.
+#[test]
+fn check_exhaustive_compare_limbs() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_2);
+    for chain in 0..27_u64 {
+        let codes = [chain / 9, chain / 3 % 3, chain % 3];
+        for lowest in 0..2_u64 {
+            let expected = match codes.iter().find(|code| **code != 1) {
+                Some(code) => *code / 2,
+                None => lowest,
+            };
+            let ct_codes: Vec<Ciphertext> = codes.iter().map(|code| client_key.encrypt(*code)).collect();
+            let result = ct_compare_limbs(&server_key, &ct_codes, client_key.encrypt(lowest));
+            assert_eq!(client_key.decrypt_message_and_carry(&result), expected, "{:?} {}", codes, lowest);
+        }
+    }
+}
+
+#[test]
+fn check_reduced_order_bits() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_2_CARRY_2);
+    let ciphers: Vec<(u8, EncodedCipherBits)> =
+        order_bytes().into_iter().map(|byte| (byte, EncodedCipherBits::encrypt(&client_key, byte))).collect();
+    check_order(&server_key, &client_key.shortint, &ciphers);
+}
+
+#[test]
+#[ignore = "exhaustive, run with --ignored"]
+fn check_exhaustive_order_2bits() {
+    check_exhaustive_order::<EncodedCipher2bits>(PARAM_MESSAGE_2_CARRY_2);
+}
+
+#[test]
+#[ignore = "exhaustive, run with --ignored"]
+fn check_exhaustive_order_4bits() {
+    check_exhaustive_order::<EncodedCipher4bits>(PARAM_MESSAGE_4_CARRY_4);
+}
+
+#[test]
+#[ignore = "exhaustive, run with --ignored"]
+fn check_exhaustive_order_8bits() {
+    check_exhaustive_order::<EncodedCipher8bits>(PARAM_MESSAGE_8_CARRY_0);
+}
+
+#[test]
+#[should_panic(expected = "message space")]
+fn check_order_needs_two_bits_of_message() {
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_1_CARRY_1);
+    let left = EncodedCipher4bits::encrypt(&client_key, 1);
+    let right = EncodedCipher4bits::encrypt(&client_key, 0);
+    left.greater_or_equal(&server_key, right);
+}
@@ -1,15 +1,305 @@
-use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+use std::borrow::Borrow;
 
+use serde::{Deserialize, Serialize};
+use tfhe::boolean::prelude::{self as boolean, BinaryBooleanGates};
+use tfhe::core_crypto::prelude::{
+    allocate_and_generate_new_lwe_keyswitch_key, keyswitch_lwe_ciphertext, lwe_ciphertext_plaintext_add_assign,
+    new_seeder, ActivatedRandomGenerator, EncryptionRandomGenerator,
+    LweCiphertext, LweKeyswitchKeyOwned, LweSecretKey, LweSecretKeyOwned, Plaintext,
+};
+use tfhe::shortint::ciphertext::Degree;
+use tfhe::shortint::{Ciphertext, ClientKey, Parameters, ServerKey};
+
+/// An encrypted byte, as the machines compare it with the characters of the
+/// program and turn the results into shortint match bits.
+///
+/// The keys are associated types rather than the shortint keys, as
+/// `EncodedCipherBits` can't be encrypted nor compared with the shortint keys
+/// alone: its client and server keys hold the boolean keys of the bytes next
+/// to the shortint ones. The machines only ever borrow the shortint keys from
+/// them, for the match bits, and the shortint encodings use the shortint keys
+/// themselves.
 pub trait EncodedCipherTrait {
-    fn encrypt(client_key: &ClientKey, c: u8) -> Self;
-    fn decrypt(self, client_key: &ClientKey) -> u8;
+    type ClientKey: Borrow<ClientKey>;
+    type ServerKey: Borrow<ServerKey>;
+
+    fn encrypt(client_key: &Self::ClientKey, c: u8) -> Self;
+    fn decrypt(self, client_key: &Self::ClientKey) -> u8;
+
+    fn equal(self, server_key: &Self::ServerKey, rhs: Self) -> Ciphertext;
+    fn greater_or_equal(self, server_key: &Self::ServerKey, rhs: Self) -> Ciphertext;
+    fn less_or_equal(self, server_key: &Self::ServerKey, rhs: Self) -> Ciphertext;
+
+    // comparisons with a cleartext byte, cheaper than with a ciphertext
+    fn scalar_equal(self, server_key: &Self::ServerKey, rhs: u8) -> Ciphertext;
+    fn scalar_greater_or_equal(self, server_key: &Self::ServerKey, rhs: u8) -> Ciphertext;
+    fn scalar_less_or_equal(self, server_key: &Self::ServerKey, rhs: u8) -> Ciphertext;
+
+    // whether the byte is set in a cleartext truth table, such as the bytes of
+    // a class, using lookup tables on the limbs
+    fn in_table(self, server_key: &Self::ServerKey, table: &[bool; 256]) -> Ciphertext;
+
+    // whether the byte is between the two others, included, as for the ranges
+    // of an encrypted class
+    fn in_range(self, server_key: &Self::ServerKey, start: Self, end: Self) -> Ciphertext
+    where
+        Self: Sized + Clone,
+    {
+        let greater = self.clone().greater_or_equal(server_key, start);
+        let less = self.less_or_equal(server_key, end);
+        and_bits(server_key.borrow(), &greater, &less)
+    }
+}
+
+/// The encodings whose bytes can be overwritten homomorphically, which
+/// `replace_all` needs. `EncodedCipherClass` is not one of them: a replacement
+/// byte has no class ID.
+pub trait SelectCipherTrait: EncodedCipherTrait {
+    // `self` if the condition encrypts 0, `replacement` if it encrypts 1
+    fn select(self, server_key: &Self::ServerKey, condition: &Ciphertext, replacement: u8) -> Self;
+}
+
+// AND of two bits as a lookup table on their sum, which needs no carry space
+// unlike `unchecked_mul_lsb`
+fn and_bits(server_key: &ServerKey, left: &Ciphertext, right: &Ciphertext) -> Ciphertext {
+    let sum = server_key.unchecked_add(left, right);
+    server_key.smart_scalar_greater_or_equal(&sum, 2)
+}
+
+// Sum of bits of which at most one is set, as the indicators of a one-hot
+// byte. Every addition adds the noise of a bit, so once the sum holds a
+// quarter of the message and carry space worth of bits it is bootstrapped back
+// to a fresh 0 or 1: `in_range` can then still add an indicator and two sums
+// before its lookup table.
+struct BitSum {
+    sum: Option<Ciphertext>,
+    bits: usize,
+}
+
+impl BitSum {
+    fn new() -> Self {
+        BitSum { sum: None, bits: 0 }
+    }
+
+    fn add(&mut self, server_key: &ServerKey, bit: &Ciphertext) {
+        let space = server_key.message_modulus.0 * server_key.carry_modulus.0;
+        self.sum = Some(match self.sum.take() {
+            Some(previous) if self.bits >= (space / 4).max(1) => {
+                self.bits = 1;
+                server_key.unchecked_add(&server_key.smart_scalar_greater_or_equal(&previous, 1), bit)
+            }
+            Some(previous) => server_key.unchecked_add(&previous, bit),
+            None => bit.clone(),
+        });
+        self.bits += 1;
+    }
+
+    fn finish(self, server_key: &ServerKey) -> Ciphertext {
+        self.sum.unwrap_or_else(|| server_key.create_trivial(0))
+    }
+}
+
+// How the membership in a table of the value written by some limbs, most
+// significant first and `bits` bits each, is evaluated. The first `packed`
+// limbs are packed into one value, as many as the message and carry space
+// holds. The values it can take leading to the same table for the other limbs
+// are grouped, each group costing one lookup table on the packed value, and
+// the membership for the other limbs with a multiplication when they aren't
+// all set.
+struct TablePlan {
+    packed: usize,
+    groups: Vec<(Vec<u64>, Option<TablePlan>)>,
+}
+
+impl TablePlan {
+    // None when no value is set
+    fn new(space: usize, limbs: usize, bits: usize, table: &[bool]) -> Option<Self> {
+        let mut packed = 1;
+        while packed < limbs && 1 << (bits * (packed + 1)) <= space {
+            packed += 1;
+        }
+        // entries of the table for each value of the packed limbs
+        let width = table.len() >> (bits * packed);
+        let mut subtables: Vec<(Vec<u64>, &[bool])> = vec![];
+        for value in 0..1_usize << (bits * packed) {
+            let subtable = &table[value * width..(value + 1) * width];
+            if !subtable.contains(&true) {
+                continue;
+            }
+            match subtables.iter_mut().find(|(_, other)| *other == subtable) {
+                Some((values, _)) => values.push(value as u64),
+                None => subtables.push((vec![value as u64], subtable)),
+            }
+        }
+        let groups: Vec<(Vec<u64>, Option<TablePlan>)> = subtables
+            .into_iter()
+            .map(|(values, subtable)| match subtable.iter().all(|set| *set) {
+                true => (values, None),
+                false => (values, TablePlan::new(space, limbs - packed, bits, subtable)),
+            })
+            .collect();
+        match groups.is_empty() {
+            true => None,
+            false => Some(TablePlan { packed, groups }),
+        }
+    }
+
+    fn bootstraps(&self) -> usize {
+        self.groups
+            .iter()
+            .map(|(_, rest)| match rest {
+                Some(rest) => 2 + rest.bootstraps(),
+                None => 1,
+            })
+            .sum()
+    }
+
+    fn evaluate(&self, server_key: &ServerKey, limbs: &[&Ciphertext], bits: usize) -> Ciphertext {
+        let (head, rest) = limbs.split_at(self.packed);
+        let packed = head[1..].iter().fold(head[0].clone(), |packed, limb| {
+            server_key.unchecked_add(&server_key.unchecked_scalar_mul(&packed, 1 << bits), limb)
+        });
+        self.groups
+            .iter()
+            .map(|(values, rest_plan)| {
+                let head = lookup(server_key, &packed, |x| values.contains(&x) as u64);
+                match rest_plan {
+                    Some(rest_plan) => {
+                        server_key.unchecked_mul_lsb(&head, &rest_plan.evaluate(server_key, rest, bits))
+                    }
+                    None => head,
+                }
+            })
+            // the groups are disjoint, at most one of them holds
+            .reduce(|previous, member| server_key.unchecked_add(&previous, &member))
+            .unwrap()
+    }
+}
+
+// Whether the value written by the limbs is set in the table, following its
+// `TablePlan`. Returns None when no value is set.
+fn ct_limbs_in_table(
+    server_key: &ServerKey,
+    limbs: &[&Ciphertext],
+    bits: usize,
+    table: &[bool],
+) -> Option<Ciphertext> {
+    let space = server_key.message_modulus.0 * server_key.carry_modulus.0;
+    TablePlan::new(space, limbs.len(), bits, table).map(|plan| plan.evaluate(server_key, limbs, bits))
+}
+
+/// Number of programmable bootstraps `in_table` costs with the encodings
+/// splitting a byte into `limbs` limbs of `bits` bits, such as
+/// `EncodedCipher4bits` with 2 limbs of 4 bits. It depends on the message and
+/// carry space of the parameters, which sets how many limbs are looked up at
+/// once.
+pub fn limbs_in_table_bootstraps(parameters: &Parameters, limbs: usize, bits: usize, table: &[bool; 256]) -> usize {
+    let space = parameters.message_modulus.0 * parameters.carry_modulus.0;
+    TablePlan::new(space, limbs, bits, table).map_or(0, |plan| plan.bootstraps())
+}
+
+/// Lexicographic `>=`, or `<=`, of two numbers written with limbs. Every limb
+/// but the lowest gives a code, most significant first: 2 when the limbs alone
+/// decide that the comparison holds, 1 when they are equal and 0 when they
+/// decide that it fails. `lowest` is the comparison of the lowest limbs. Going
+/// up from the lowest limb, the result so far r becomes code + r >= 2, which is
+/// 1 exactly when the limbs decide or are equal and r is 1: the result stays 0
+/// or 1 whatever the number of limbs, with code + r at most 3. That is the
+/// whole carry budget, and it must fit in the message space of the lookup.
+///
+/// Only the encodings writing the byte with shortint limbs go through it:
+/// `EncodedCipher2bits` and `EncodedCipher4bits` with `ct_limbs_order`, and
+/// `EncodedCipher8bits` with its top seven bits as the one limb above the
+/// lowest bit. `EncodedCipherBits` compares its bits with gate circuits,
+/// `EncodedCipherOneHot` with sums of indicators and `EncodedCipherClass` with
+/// a single lookup table, none of them on limbs.
+pub fn ct_compare_limbs(server_key: &ServerKey, codes: &[Ciphertext], lowest: Ciphertext) -> Ciphertext {
+    assert!(
+        server_key.message_modulus.0 >= 4,
+        "comparing limbs needs a message space of at least 2 bits"
+    );
+    codes.iter().rev().fold(lowest, |result, code| {
+        let sum = server_key.unchecked_add(code, &result);
+        server_key.smart_scalar_greater_or_equal(&sum, 2)
+    })
+}
+
+// 2 * decides + equal, the two bits being exclusive
+fn limb_code(server_key: &ServerKey, decides: &Ciphertext, equal: &Ciphertext) -> Ciphertext {
+    server_key.unchecked_add(&server_key.unchecked_scalar_mul(decides, 2), equal)
+}
+
+/// `>=` when `greater`, `<=` otherwise, of numbers written with the limbs, most
+/// significant first, comparing the limbs with bivariate lookup tables
+pub fn ct_limbs_order(server_key: &ServerKey, lhs: &[&Ciphertext], rhs: &[&Ciphertext], greater: bool) -> Ciphertext {
+    // a bivariate lookup table packs the left limb times the degree of the
+    // right one plus one, added to the right limb, in a single ciphertext
+    let space = server_key.message_modulus.0 * server_key.carry_modulus.0;
+    for (left, right) in lhs.iter().zip(rhs.iter()) {
+        assert!(
+            (left.degree.0 + 1) * (right.degree.0 + 1) <= space,
+            "comparing limbs of degrees {} and {} needs a carry space as large as their message space",
+            left.degree.0,
+            right.degree.0
+        );
+    }
+    let (lhs_lowest, lhs) = lhs.split_last().unwrap();
+    let (rhs_lowest, rhs) = rhs.split_last().unwrap();
+    let codes: Vec<Ciphertext> = lhs
+        .iter()
+        .zip(rhs.iter())
+        .map(|(left, right)| {
+            let decides = match greater {
+                true => server_key.unchecked_greater(left, right),
+                false => server_key.unchecked_less(left, right),
+            };
+            limb_code(server_key, &decides, &server_key.unchecked_equal(left, right))
+        })
+        .collect();
+    let lowest = match greater {
+        true => server_key.unchecked_greater_or_equal(lhs_lowest, rhs_lowest),
+        false => server_key.unchecked_less_or_equal(lhs_lowest, rhs_lowest),
+    };
+    ct_compare_limbs(server_key, &codes, lowest)
+}
 
-    fn equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext;
-    fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext;
-    fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext;
+/// Same as `ct_limbs_order`, the right-hand side limbs being cleartext: the
+/// code of a limb is then a single lookup
+pub fn ct_limbs_scalar_order(server_key: &ServerKey, lhs: &[&Ciphertext], rhs: &[u8], greater: bool) -> Ciphertext {
+    let (lhs_lowest, lhs) = lhs.split_last().unwrap();
+    let (rhs_lowest, rhs) = rhs.split_last().unwrap();
+    let codes: Vec<Ciphertext> = lhs
+        .iter()
+        .zip(rhs.iter())
+        .map(|(left, right)| {
+            let right = *right as u64;
+            lookup(server_key, left, |x| match x == right {
+                true => 1,
+                false => 2 * (if greater { x > right } else { x < right }) as u64,
+            })
+        })
+        .collect();
+    let lowest = match greater {
+        true => server_key.smart_scalar_greater_or_equal(lhs_lowest, *rhs_lowest),
+        false => server_key.smart_scalar_less_or_equal(lhs_lowest, *rhs_lowest),
+    };
+    ct_compare_limbs(server_key, &codes, lowest)
+}
+
+// limb * (1 - condition) + replacement * condition, one of the terms being 0
+fn select_limb(
+    server_key: &ServerKey,
+    limb: &Ciphertext,
+    condition: &Ciphertext,
+    not_condition: &Ciphertext,
+    replacement: u8,
+) -> Ciphertext {
+    let kept = server_key.unchecked_mul_lsb(limb, not_condition);
+    let replaced = server_key.unchecked_scalar_mul(condition, replacement);
+    server_key.unchecked_add(&kept, &replaced)
 }
 
-pub fn convert_str_to_cts<T:EncodedCipherTrait>(input: &str, client_key: &ClientKey) -> Vec<T> {
+pub fn convert_str_to_cts<T:EncodedCipherTrait>(input: &str, client_key: &T::ClientKey) -> Vec<T> {
     input
     .bytes()
         .map(|c| {
@@ -18,6 +308,57 @@ pub fn convert_str_to_cts<T:EncodedCipherTrait>(input: &str, client_key: &Client
         .collect()
 }
 
+/// Encrypts the input in one-hot encoding over the given alphabet, see
+/// `EncodedCipherOneHot`.
+pub fn convert_str_to_one_hot_cts(
+    input: &str,
+    alphabet: &[u8],
+    client_key: &ClientKey,
+) -> Vec<EncodedCipherOneHot> {
+    input
+        .bytes()
+        .map(|c| EncodedCipherOneHot::encrypt_over(client_key, c, alphabet))
+        .collect()
+}
+
+/// Encrypts the input as the IDs of the classes of its bytes, `classes` giving
+/// the class of every byte.
+pub fn convert_str_to_class_cts(
+    input: &str,
+    classes: &[u8; 256],
+    client_key: &ClientKey,
+) -> Vec<EncodedCipherClass> {
+    input
+        .bytes()
+        .map(|c| EncodedCipherClass::encrypt(client_key, classes[c as usize]))
+        .collect()
+}
+
+/// Encrypted input padded to a fixed length, so that its own length stays
+/// secret. `valid[i]` encrypts 1 when `chars[i]` is a character of the input
+/// and 0 when it is padding.
+pub struct PaddedInput<T> {
+    pub chars: Vec<T>,
+    pub valid: Vec<Ciphertext>,
+}
+
+/// Encrypts the input padded with zero bytes up to `length`, which should be
+/// chosen among a few bucket sizes shared by all inputs.
+pub fn convert_str_to_padded_cts<T:EncodedCipherTrait>(
+    input: &str,
+    length: usize,
+    client_key: &T::ClientKey,
+) -> PaddedInput<T> {
+    assert!(input.len() <= length, "the input is longer than the padded length");
+    let bytes = input.bytes().chain(std::iter::repeat(0)).take(length);
+    PaddedInput {
+        chars: bytes.map(|c| T::encrypt(client_key, c)).collect(),
+        valid: (0..length)
+            .map(|position| client_key.borrow().encrypt((position < input.len()) as u64))
+            .collect(),
+    }
+}
+
 
 #[derive(Clone)]
 pub struct EncodedCipher4bits {
@@ -26,6 +367,9 @@ pub struct EncodedCipher4bits {
 }
 
 impl EncodedCipherTrait for EncodedCipher4bits {
+    type ClientKey = ClientKey;
+    type ServerKey = ServerKey;
+
     fn encrypt(client_key: &ClientKey, c: u8) -> Self {
         let upper = client_key.encrypt(((c >> 4) & 0x0F) as u64);
         let lower = client_key.encrypt((c & 0x0F) as u64);
@@ -48,20 +392,42 @@ impl EncodedCipherTrait for EncodedCipher4bits {
     }
 
     fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
-        let result_upper = server_key.unchecked_greater(&self.upper, &rhs.upper);
-        let equal_upper = server_key.unchecked_equal(&self.upper, &rhs.upper);
-        let result_lower = server_key.unchecked_greater_or_equal(&self.lower, &rhs.lower);
-        let result = server_key.unchecked_mul_lsb(&equal_upper, &&result_lower);
-        server_key.unchecked_add(&result_upper, &result)
+        ct_limbs_order(server_key, &[&self.upper, &self.lower], &[&rhs.upper, &rhs.lower], true)
     }
 
     fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
-        let result_upper = server_key.unchecked_less(&self.upper, &rhs.upper);
-        let equal_upper = server_key.unchecked_equal(&self.upper, &rhs.upper);
-        let result_lower = server_key.unchecked_less_or_equal(&self.lower, &rhs.lower);
+        ct_limbs_order(server_key, &[&self.upper, &self.lower], &[&rhs.upper, &rhs.lower], false)
+    }
+
+    fn scalar_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let equal_lower = server_key.smart_scalar_equal(&self.lower, rhs & 0x0F);
+        let equal_upper = server_key.smart_scalar_equal(&self.upper, (rhs >> 4) & 0x0F);
+        server_key.unchecked_mul_lsb(&equal_lower, &equal_upper)
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let limbs = [(rhs >> 4) & 0x0F, rhs & 0x0F];
+        ct_limbs_scalar_order(server_key, &[&self.upper, &self.lower], &limbs, true)
+    }
+
+    fn scalar_less_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let limbs = [(rhs >> 4) & 0x0F, rhs & 0x0F];
+        ct_limbs_scalar_order(server_key, &[&self.upper, &self.lower], &limbs, false)
+    }
 
-        let result = server_key.unchecked_mul_lsb(&equal_upper, &result_lower);
-        server_key.unchecked_add(&result_upper, &result)
+    fn in_table(self, server_key: &ServerKey, table: &[bool; 256]) -> Ciphertext {
+        ct_limbs_in_table(server_key, &[&self.upper, &self.lower], 4, table)
+            .unwrap_or_else(|| server_key.create_trivial(0))
+    }
+}
+
+impl SelectCipherTrait for EncodedCipher4bits {
+    fn select(self, server_key: &ServerKey, condition: &Ciphertext, replacement: u8) -> Self {
+        let not_condition = server_key.smart_scalar_equal(condition, 0);
+        EncodedCipher4bits {
+            upper: select_limb(server_key, &self.upper, condition, &not_condition, (replacement >> 4) & 0x0F),
+            lower: select_limb(server_key, &self.lower, condition, &not_condition, replacement & 0x0F),
+        }
     }
 }
 
@@ -76,6 +442,9 @@ pub struct EncodedCipher2bits {
 }
 
 impl EncodedCipherTrait for EncodedCipher2bits {
+    type ClientKey = ClientKey;
+    type ServerKey = ServerKey;
+
     fn encrypt(client_key: &ClientKey, c: u8) -> Self {
         let i = client_key.encrypt(((c >> 6) & 0x03) as u64);
         let j = client_key.encrypt(((c >> 4) & 0x03) as u64);
@@ -103,46 +472,710 @@ impl EncodedCipherTrait for EncodedCipher2bits {
     }
 
     fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
-        // (Ai > Bi) + (Ai == Bi) *
-        //          (Aj > Bj) + (Aj == Bj) *
-        //                  (Ak > Bk) + (Ak == Bk) *
-        //                          (Al >= Bl) 
-        let result_i = server_key.unchecked_greater(&self.i, &rhs.i);
-        let result_i_equal = server_key.unchecked_equal(&self.i, &rhs.i);
-        let result_j = server_key.unchecked_greater(&self.j, &rhs.j);
-        let result_j_equal = server_key.unchecked_equal(&self.j, &rhs.j);
-        let result_k = server_key.unchecked_greater(&self.k, &rhs.k);
-        let result_k_equal = server_key.unchecked_equal(&self.k, &rhs.k);
-        let result_l = server_key.unchecked_greater_or_equal(&self.l, &rhs.l);
-
-        let result = server_key.unchecked_mul_lsb(&result_k_equal, &result_l);
-        let result = server_key.unchecked_add(&result_k, &result);
-        let result = server_key.unchecked_mul_lsb(&result_j_equal, &result);
-        let result = server_key.unchecked_add(&result_j, &result);
-        let result = server_key.smart_scalar_greater_or_equal(&result, 1_u8);
-        let result = server_key.unchecked_mul_lsb(&result_i_equal, &result);
-        server_key.unchecked_add(&result_i, &result)
+        let limbs = [&self.i, &self.j, &self.k, &self.l];
+        ct_limbs_order(server_key, &limbs, &[&rhs.i, &rhs.j, &rhs.k, &rhs.l], true)
+    }
+
+    fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        let limbs = [&self.i, &self.j, &self.k, &self.l];
+        ct_limbs_order(server_key, &limbs, &[&rhs.i, &rhs.j, &rhs.k, &rhs.l], false)
+    }
+
+    fn scalar_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let result_i = server_key.smart_scalar_equal(&self.i, (rhs >> 6) & 0x03);
+        let result_j = server_key.smart_scalar_equal(&self.j, (rhs >> 4) & 0x03);
+        let result_k = server_key.smart_scalar_equal(&self.k, (rhs >> 2) & 0x03);
+        let result_l = server_key.smart_scalar_equal(&self.l, rhs & 0x03);
+        let result_upper = server_key.unchecked_mul_lsb(&result_i, &result_j);
+        let result_lower = server_key.unchecked_mul_lsb(&result_k, &result_l);
+        server_key.unchecked_mul_lsb(&result_upper, &result_lower)
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let limbs = [(rhs >> 6) & 0x03, (rhs >> 4) & 0x03, (rhs >> 2) & 0x03, rhs & 0x03];
+        ct_limbs_scalar_order(server_key, &[&self.i, &self.j, &self.k, &self.l], &limbs, true)
+    }
+
+    fn scalar_less_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        let limbs = [(rhs >> 6) & 0x03, (rhs >> 4) & 0x03, (rhs >> 2) & 0x03, rhs & 0x03];
+        ct_limbs_scalar_order(server_key, &[&self.i, &self.j, &self.k, &self.l], &limbs, false)
+    }
+
+    fn in_table(self, server_key: &ServerKey, table: &[bool; 256]) -> Ciphertext {
+        ct_limbs_in_table(server_key, &[&self.i, &self.j, &self.k, &self.l], 2, table)
+            .unwrap_or_else(|| server_key.create_trivial(0))
+    }
+}
+
+impl SelectCipherTrait for EncodedCipher2bits {
+    fn select(self, server_key: &ServerKey, condition: &Ciphertext, replacement: u8) -> Self {
+        let not_condition = server_key.smart_scalar_equal(condition, 0);
+        EncodedCipher2bits {
+            i: select_limb(server_key, &self.i, condition, &not_condition, (replacement >> 6) & 0x03),
+            j: select_limb(server_key, &self.j, condition, &not_condition, (replacement >> 4) & 0x03),
+            k: select_limb(server_key, &self.k, condition, &not_condition, (replacement >> 2) & 0x03),
+            l: select_limb(server_key, &self.l, condition, &not_condition, replacement & 0x03),
+        }
+    }
+}
+
+/// The whole byte in a single ciphertext, for parameter sets with 8 bits of
+/// message such as PARAM_MESSAGE_8_CARRY_0. Comparisons with a cleartext byte
+/// and class tests are a single lookup table. With no carry space left for
+/// bivariate lookup tables, comparing two ciphertexts goes through
+/// `compare_parts`, itself 4 programmable bootstraps.
+///
+/// `scalar_equal`, the other scalar comparisons and `in_table` cost 1
+/// programmable bootstrap, `equal` 6, `greater_or_equal` and `less_or_equal` 7
+/// and `select` 4. Patterns comparing the input with encrypted characters are
+/// cheaper with `EncodedCipher4bits`, whose carry space allows bivariate
+/// lookup tables.
+#[derive(Clone)]
+pub struct EncodedCipher8bits {
+    value: Ciphertext,
+}
+
+fn lookup(server_key: &ServerKey, value: &Ciphertext, f: impl Fn(u64) -> u64) -> Ciphertext {
+    let accumulator = server_key.generate_accumulator(f);
+    server_key.keyswitch_programmable_bootstrap(value, &accumulator)
+}
+
+impl EncodedCipher8bits {
+    // Splits the comparison of the bytes into their top seven bits and their
+    // lowest bit, without any value going over 8 bits: the part of one byte is
+    // added to the complement of the other's, the sums being 127 and 1 when the
+    // parts are equal, and greater when the part of `self` is.
+    fn compare_parts(self, server_key: &ServerKey, rhs: Self) -> (Ciphertext, Ciphertext) {
+        let top = server_key.unchecked_add(
+            &lookup(server_key, &self.value, |x| x >> 1),
+            &lookup(server_key, &rhs.value, |x| 127 - (x >> 1)),
+        );
+        let lowest = server_key.unchecked_add(
+            &lookup(server_key, &self.value, |x| x & 1),
+            &lookup(server_key, &rhs.value, |x| 1 - (x & 1)),
+        );
+        (top, lowest)
+    }
+
+    // `>=` when `greater`, `<=` otherwise, the top seven bits being one limb,
+    // for the 4 bootstraps of `compare_parts` and 3 more
+    fn order(self, server_key: &ServerKey, rhs: Self, greater: bool) -> Ciphertext {
+        let (top, lowest) = self.compare_parts(server_key, rhs);
+        let code = lookup(server_key, &top, |sum| match sum == 127 {
+            true => 1,
+            false => 2 * ((sum > 127) == greater) as u64,
+        });
+        let lowest = lookup(server_key, &lowest, |sum| (if greater { sum >= 1 } else { sum <= 1 }) as u64);
+        ct_compare_limbs(server_key, &[code], lowest)
+    }
+}
+
+impl EncodedCipherTrait for EncodedCipher8bits {
+    type ClientKey = ClientKey;
+    type ServerKey = ServerKey;
+
+    fn encrypt(client_key: &ClientKey, c: u8) -> Self {
+        EncodedCipher8bits {
+            value: client_key.encrypt(c as u64),
+        }
+    }
+
+    fn decrypt(self, client_key: &ClientKey) -> u8 {
+        client_key.decrypt(&self.value) as u8
+    }
+
+    fn equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        // 3 only when the top bits sum to 127 and the lowest to 1, for the 4
+        // bootstraps of `compare_parts` and 2 more
+        let (top, lowest) = self.compare_parts(server_key, rhs);
+        let top = lookup(server_key, &top, |sum| 2 * (sum == 127) as u64);
+        server_key.smart_scalar_equal(&server_key.unchecked_add(&top, &lowest), 3)
+    }
+
+    fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        self.order(server_key, rhs, true)
+    }
+
+    fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        self.order(server_key, rhs, false)
+    }
+
+    fn scalar_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_equal(&self.value, rhs)
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_greater_or_equal(&self.value, rhs)
+    }
+
+    fn scalar_less_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_less_or_equal(&self.value, rhs)
+    }
+
+    fn in_table(self, server_key: &ServerKey, table: &[bool; 256]) -> Ciphertext {
+        ct_limbs_in_table(server_key, &[&self.value], 8, table)
+            .unwrap_or_else(|| server_key.create_trivial(0))
+    }
+}
+
+impl SelectCipherTrait for EncodedCipher8bits {
+    fn select(self, server_key: &ServerKey, condition: &Ciphertext, replacement: u8) -> Self {
+        // each half of the byte plus 16 times the condition still fits in 8
+        // bits, and a lookup table keeps the half or takes the replacement's:
+        // 2 bootstraps per half
+        let offset = server_key.unchecked_scalar_mul(condition, 16);
+        let select_half = |shift: u64| {
+            let half = lookup(server_key, &self.value, |x| (x >> shift) & 0x0F);
+            let half = server_key.unchecked_add(&half, &offset);
+            let replacement = (replacement as u64 >> shift) & 0x0F;
+            lookup(server_key, &half, |x| (if x >= 16 { replacement } else { x }) << shift)
+        };
+        EncodedCipher8bits {
+            value: server_key.unchecked_add(&select_half(4), &select_half(0)),
+        }
+    }
+}
+
+/// The byte as eight `tfhe::boolean` ciphertexts, least significant bit first,
+/// compared with gate circuits: an XNOR tree for equality and ripple
+/// comparators from the least significant bit for the orderings.
+///
+/// The result of a circuit is switched to the shortint LWE key and bootstrapped
+/// into a match bit, see `BitsServerKey`, so each comparison costs one shortint
+/// bootstrap on top of its gates.
+#[derive(Clone)]
+pub struct EncodedCipherBits {
+    bits: Vec<boolean::Ciphertext>,
+}
+
+/// Client key of `EncodedCipherBits`: the boolean key of the bytes and the
+/// shortint key of the match bits.
+#[derive(Serialize, Deserialize)]
+pub struct BitsClientKey {
+    pub boolean: boolean::ClientKey,
+    pub shortint: ClientKey,
+}
+
+/// Server key of `EncodedCipherBits`: the boolean and shortint server keys, and
+/// key switching keys between the boolean LWE key and the LWE key of the
+/// shortint ciphertexts, which turn the results of the gate circuits into
+/// match bits and the conditions of `select` into boolean ciphertexts.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BitsServerKey {
+    pub boolean: boolean::ServerKey,
+    pub shortint: ServerKey,
+    to_shortint: LweKeyswitchKeyOwned<u64>,
+    to_boolean: LweKeyswitchKeyOwned<u64>,
+}
+
+impl Borrow<ClientKey> for BitsClientKey {
+    fn borrow(&self) -> &ClientKey {
+        &self.shortint
+    }
+}
+
+impl Borrow<ServerKey> for BitsServerKey {
+    fn borrow(&self) -> &ServerKey {
+        &self.shortint
+    }
+}
+
+/// Generates the keys of `EncodedCipherBits`, the shortint ones with the given
+/// parameters and the boolean ones with the default boolean parameters.
+pub fn gen_bits_keys(parameters: Parameters) -> (BitsClientKey, BitsServerKey) {
+    let (boolean_client_key, boolean_server_key) = boolean::gen_keys();
+    let (shortint_client_key, shortint_server_key) = tfhe::shortint::gen_keys(parameters);
+    let boolean_key = boolean_secret_key(&boolean_client_key);
+    let shortint_key = shortint_secret_key(&shortint_client_key);
+
+    let mut seeder = new_seeder();
+    let seed = seeder.seed();
+    let mut generator = EncryptionRandomGenerator::<ActivatedRandomGenerator>::new(seed, seeder.as_mut());
+    let to_shortint = allocate_and_generate_new_lwe_keyswitch_key(
+        &boolean_key,
+        &shortint_key,
+        parameters.ks_base_log,
+        parameters.ks_level,
+        parameters.glwe_modular_std_dev,
+        &mut generator,
+    );
+    let to_boolean = allocate_and_generate_new_lwe_keyswitch_key(
+        &shortint_key,
+        &boolean_key,
+        parameters.ks_base_log,
+        parameters.ks_level,
+        boolean::DEFAULT_PARAMETERS.lwe_modular_std_dev,
+        &mut generator,
+    );
+
+    (
+        BitsClientKey {
+            boolean: boolean_client_key,
+            shortint: shortint_client_key,
+        },
+        BitsServerKey {
+            boolean: boolean_server_key,
+            shortint: shortint_server_key,
+            to_shortint,
+            to_boolean,
+        },
+    )
+}
+
+// tfhe doesn't expose the LWE secret keys the key switching keys are made of,
+// but decrypts any LWE ciphertext: with a mask of a single coefficient, the
+// decryption depends on the bit of the key under that coefficient only, and
+// reads the key one bit at a time.
+//
+// The boolean key, the one of the gate outputs: the phase 1/4 - bit/2 decrypts
+// to true when the bit is 0.
+fn boolean_secret_key(client_key: &boolean::ClientKey) -> LweSecretKeyOwned<u64> {
+    let dimension = boolean::DEFAULT_PARAMETERS.lwe_dimension.0;
+    let bits = (0..dimension).map(|index| {
+        let mut container = vec![0_u32; dimension + 1];
+        container[index] = 1 << 31;
+        container[dimension] = 1 << 30;
+        !client_key.decrypt(&boolean::Ciphertext::Encrypted(LweCiphertext::from_container(container))) as u64
+    });
+    LweSecretKey::from_container(bits.collect::<Vec<u64>>())
+}
+
+// The shortint key the bootstraps output to and the key switch of
+// `keyswitch_programmable_bootstrap` starts from: the phase bit * delta
+// decrypts to the bit.
+fn shortint_secret_key(client_key: &ClientKey) -> LweSecretKeyOwned<u64> {
+    let parameters = client_key.parameters;
+    let dimension = parameters.glwe_dimension.0 * parameters.polynomial_size.0;
+    let delta = (1_u64 << 63) / (parameters.message_modulus.0 * parameters.carry_modulus.0) as u64;
+    let bits = (0..dimension).map(|index| {
+        let mut container = vec![0_u64; dimension + 1];
+        container[index] = delta.wrapping_neg();
+        client_key.decrypt_message_and_carry(&Ciphertext {
+            ct: LweCiphertext::from_container(container),
+            degree: Degree(1),
+            message_modulus: parameters.message_modulus,
+            carry_modulus: parameters.carry_modulus,
+        })
+    });
+    LweSecretKey::from_container(bits.collect::<Vec<u64>>())
+}
+
+impl BitsServerKey {
+    // The match bit of a boolean ciphertext. Boolean ciphertexts encrypt +1/8
+    // of the torus for true and -1/8 for false on 32 bits: lifted to 64 bits,
+    // switched to the shortint key and shifted by 3/16, they are at 5/16 and
+    // 1/16, which a bootstrap with a threshold in between maps to 1 and 0.
+    fn match_bit(&self, bit: &boolean::Ciphertext) -> Ciphertext {
+        let lwe = match bit {
+            boolean::Ciphertext::Trivial(value) => return self.shortint.create_trivial(*value as u64),
+            boolean::Ciphertext::Encrypted(lwe) => lwe,
+        };
+        let lifted = LweCiphertext::from_container(lwe.as_ref().iter().map(|c| (*c as u64) << 32).collect::<Vec<u64>>());
+        let mut switched = LweCiphertext::new(0_u64, self.to_shortint.output_key_lwe_dimension().to_lwe_size());
+        keyswitch_lwe_ciphertext(&self.to_shortint, &lifted, &mut switched);
+        lwe_ciphertext_plaintext_add_assign(&mut switched, Plaintext(3 << 60));
+
+        let space = self.shortint.message_modulus.0 * self.shortint.carry_modulus.0;
+        let accumulator = self.shortint.generate_accumulator(|x| (8 * x >= 3 * space as u64) as u64);
+        let switched = Ciphertext {
+            ct: switched,
+            degree: Degree(space - 1),
+            message_modulus: self.shortint.message_modulus,
+            carry_modulus: self.shortint.carry_modulus,
+        };
+        self.shortint.keyswitch_programmable_bootstrap(&switched, &accumulator)
+    }
+
+    // The boolean ciphertext of a shortint bit, the other way around: a
+    // lookup table maps 1 to a quarter of the torus and 0 to 0, which switched
+    // to the boolean key, shifted by -1/8 and rounded to 32 bits are the
+    // encodings of true and false.
+    fn condition_bit(&self, bit: &Ciphertext) -> boolean::Ciphertext {
+        let space = (self.shortint.message_modulus.0 * self.shortint.carry_modulus.0) as u64;
+        let accumulator = self.shortint.generate_accumulator(|x| if x == 1 { space / 2 } else { 0 });
+        let quarter = self.shortint.keyswitch_programmable_bootstrap(bit, &accumulator);
+        let mut switched = LweCiphertext::new(0_u64, self.to_boolean.output_key_lwe_dimension().to_lwe_size());
+        keyswitch_lwe_ciphertext(&self.to_boolean, &quarter.ct, &mut switched);
+        lwe_ciphertext_plaintext_add_assign(&mut switched, Plaintext((1_u64 << 61).wrapping_neg()));
+
+        let rounded = switched.into_container().into_iter().map(|c| (c.wrapping_add(1 << 31) >> 32) as u32);
+        boolean::Ciphertext::Encrypted(LweCiphertext::from_container(rounded.collect::<Vec<u32>>()))
+    }
+}
+
+impl EncodedCipherBits {
+    fn equal_bit(&self, server_key: &boolean::ServerKey, rhs: &Self) -> boolean::Ciphertext {
+        let bits = self.bits.iter().zip(rhs.bits.iter());
+        and_tree(server_key, bits.map(|(left, right)| server_key.xnor(left, right)).collect())
+    }
+
+    fn greater_or_equal_bit(&self, server_key: &boolean::ServerKey, rhs: &Self) -> boolean::Ciphertext {
+        // where the bits differ, the byte with the bit set is the greater one
+        let bits = self.bits.iter().zip(rhs.bits.iter());
+        bits.fold(server_key.trivial_encrypt(true), |result, (left, right)| {
+            server_key.mux(&server_key.xnor(left, right), &result, left)
+        })
+    }
+
+    fn scalar_equal_bit(&self, server_key: &boolean::ServerKey, rhs: u8) -> boolean::Ciphertext {
+        let bits = self.bits.iter().enumerate().map(|(bit, ct_bit)| match (rhs >> bit) & 1 {
+            1 => ct_bit.clone(),
+            _ => server_key.not(ct_bit),
+        });
+        and_tree(server_key, bits.collect())
+    }
+
+    fn scalar_greater_or_equal_bit(&self, server_key: &boolean::ServerKey, rhs: u8) -> boolean::Ciphertext {
+        // a single gate per bit: a set bit of `rhs` is only reached with a set
+        // bit, an unset one always is
+        let bits = self.bits.iter().enumerate();
+        bits.fold(server_key.trivial_encrypt(true), |result, (bit, ct_bit)| match (rhs >> bit) & 1 {
+            1 => server_key.and(ct_bit, &result),
+            _ => server_key.or(ct_bit, &result),
+        })
+    }
+
+    fn scalar_less_or_equal_bit(&self, server_key: &boolean::ServerKey, rhs: u8) -> boolean::Ciphertext {
+        let bits = self.bits.iter().enumerate();
+        bits.fold(server_key.trivial_encrypt(true), |result, (bit, ct_bit)| match (rhs >> bit) & 1 {
+            1 => server_key.or(&server_key.not(ct_bit), &result),
+            _ => server_key.and(&server_key.not(ct_bit), &result),
+        })
+    }
+}
+
+impl EncodedCipherTrait for EncodedCipherBits {
+    type ClientKey = BitsClientKey;
+    type ServerKey = BitsServerKey;
+
+    fn encrypt(client_key: &BitsClientKey, c: u8) -> Self {
+        EncodedCipherBits {
+            bits: (0..8).map(|bit| client_key.boolean.encrypt((c >> bit) & 1 == 1)).collect(),
+        }
+    }
+
+    fn decrypt(self, client_key: &BitsClientKey) -> u8 {
+        self.bits
+            .iter()
+            .enumerate()
+            .map(|(bit, ct_bit)| (client_key.boolean.decrypt(ct_bit) as u8) << bit)
+            .sum()
+    }
+
+    fn equal(self, server_key: &BitsServerKey, rhs: Self) -> Ciphertext {
+        server_key.match_bit(&self.equal_bit(&server_key.boolean, &rhs))
+    }
+
+    fn greater_or_equal(self, server_key: &BitsServerKey, rhs: Self) -> Ciphertext {
+        server_key.match_bit(&self.greater_or_equal_bit(&server_key.boolean, &rhs))
+    }
+
+    fn less_or_equal(self, server_key: &BitsServerKey, rhs: Self) -> Ciphertext {
+        server_key.match_bit(&rhs.greater_or_equal_bit(&server_key.boolean, &self))
+    }
+
+    fn scalar_equal(self, server_key: &BitsServerKey, rhs: u8) -> Ciphertext {
+        server_key.match_bit(&self.scalar_equal_bit(&server_key.boolean, rhs))
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &BitsServerKey, rhs: u8) -> Ciphertext {
+        server_key.match_bit(&self.scalar_greater_or_equal_bit(&server_key.boolean, rhs))
+    }
+
+    fn scalar_less_or_equal(self, server_key: &BitsServerKey, rhs: u8) -> Ciphertext {
+        server_key.match_bit(&self.scalar_less_or_equal_bit(&server_key.boolean, rhs))
+    }
+
+    // OR of the runs of set bytes of the table, each between two scalar
+    // comparisons, and a single bootstrap into the match bit
+    fn in_table(self, server_key: &BitsServerKey, table: &[bool; 256]) -> Ciphertext {
+        let gates = &server_key.boolean;
+        let mut result: Option<boolean::Ciphertext> = None;
+        let mut byte = 0;
+        while byte < 256 {
+            if !table[byte] {
+                byte += 1;
+                continue;
+            }
+            let start = byte;
+            while byte < 256 && table[byte] {
+                byte += 1;
+            }
+            let (start, end) = (start as u8, (byte - 1) as u8);
+            let in_run = match (start, end) {
+                (0, 255) => gates.trivial_encrypt(true),
+                (0, _) => self.scalar_less_or_equal_bit(gates, end),
+                (_, 255) => self.scalar_greater_or_equal_bit(gates, start),
+                _ if start == end => self.scalar_equal_bit(gates, start),
+                _ => gates.and(
+                    &self.scalar_greater_or_equal_bit(gates, start),
+                    &self.scalar_less_or_equal_bit(gates, end),
+                ),
+            };
+            result = Some(match result {
+                Some(previous) => gates.or(&previous, &in_run),
+                None => in_run,
+            });
+        }
+        server_key.match_bit(&result.unwrap_or_else(|| gates.trivial_encrypt(false)))
+    }
+}
+
+impl SelectCipherTrait for EncodedCipherBits {
+    // one gate per bit once the condition is a boolean ciphertext
+    fn select(self, server_key: &BitsServerKey, condition: &Ciphertext, replacement: u8) -> Self {
+        let gates = &server_key.boolean;
+        let condition = server_key.condition_bit(condition);
+        let not_condition = gates.not(&condition);
+        let bits = self.bits.iter().enumerate().map(|(bit, ct_bit)| match (replacement >> bit) & 1 {
+            1 => gates.or(ct_bit, &condition),
+            _ => gates.and(ct_bit, &not_condition),
+        });
+        EncodedCipherBits { bits: bits.collect() }
+    }
+}
+
+// ANDs the bits pairwise, for a depth logarithmic in their number
+fn and_tree(server_key: &boolean::ServerKey, mut bits: Vec<boolean::Ciphertext>) -> boolean::Ciphertext {
+    while bits.len() > 1 {
+        bits = bits
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => server_key.and(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    bits.pop().unwrap_or_else(|| server_key.trivial_encrypt(true))
+}
+
+/// The byte as one encrypted indicator per byte of an alphabet, the one of the
+/// byte being the only one set. Class tests and comparisons with a cleartext
+/// byte are sums of indicators, with only a lookup table every few indicators
+/// to refresh the sum; comparisons with an encrypted byte cost one lookup
+/// table per byte of the alphabet.
+///
+/// The alphabet is public and defaults to all the bytes. A reduced alphabet,
+/// such as the printable ASCII characters, makes for fewer indicators. The
+/// bytes out of it have none set: they belong to no class and compare as
+/// different from every byte, so the alphabet should cover the input.
+#[derive(Clone)]
+pub struct EncodedCipherOneHot {
+    // sorted
+    alphabet: Vec<u8>,
+    indicators: Vec<Ciphertext>,
+}
+
+impl EncodedCipherOneHot {
+    pub fn encrypt_over(client_key: &ClientKey, c: u8, alphabet: &[u8]) -> Self {
+        let mut alphabet = alphabet.to_vec();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+        let indicators = alphabet
+            .iter()
+            .map(|byte| client_key.encrypt((*byte == c) as u64))
+            .collect();
+        EncodedCipherOneHot { alphabet, indicators }
+    }
+
+    fn indicator(&self, byte: u8) -> Option<&Ciphertext> {
+        let index = self.alphabet.binary_search(&byte).ok()?;
+        Some(&self.indicators[index])
+    }
+
+    // sum of the indicators of the bytes satisfying the predicate, which is 1
+    // when the byte does and 0 otherwise
+    fn sum_where(&self, server_key: &ServerKey, predicate: impl Fn(u8) -> bool) -> Ciphertext {
+        let mut sum = BitSum::new();
+        for (byte, indicator) in self.alphabet.iter().zip(self.indicators.iter()) {
+            if predicate(*byte) {
+                sum.add(server_key, indicator);
+            }
+        }
+        sum.finish(server_key)
+    }
+
+    // For each byte of the alphabet, whether `rhs` is lower or equal, or
+    // greater or equal when `ascending` is false, as running sums of its
+    // indicators. None when `rhs` has no byte on that side.
+    fn running_sums(&self, server_key: &ServerKey, rhs: &Self, ascending: bool) -> Vec<Option<Ciphertext>> {
+        let mut rhs_bytes: Vec<(u8, &Ciphertext)> =
+            rhs.alphabet.iter().copied().zip(rhs.indicators.iter()).collect();
+        let mut bytes: Vec<u8> = self.alphabet.clone();
+        if !ascending {
+            rhs_bytes.reverse();
+            bytes.reverse();
+        }
+        let mut rhs_bytes = rhs_bytes.into_iter().peekable();
+        let mut sum = BitSum::new();
+        let mut sums: Vec<Option<Ciphertext>> = bytes
+            .iter()
+            .map(|byte| {
+                let reached = |rhs_byte: u8| if ascending { rhs_byte <= *byte } else { rhs_byte >= *byte };
+                while let Some((_, indicator)) = rhs_bytes.next_if(|(rhs_byte, _)| reached(*rhs_byte)) {
+                    sum.add(server_key, indicator);
+                }
+                sum.sum.clone()
+            })
+            .collect();
+        if !ascending {
+            sums.reverse();
+        }
+        sums
+    }
+
+    // sum over the alphabet of the indicator ANDed with the bit for that byte
+    fn sum_and(&self, server_key: &ServerKey, bits: &[Option<Ciphertext>]) -> Ciphertext {
+        let mut sum = BitSum::new();
+        for (indicator, bit) in self.indicators.iter().zip(bits.iter()) {
+            if let Some(bit) = bit {
+                sum.add(server_key, &and_bits(server_key, indicator, bit));
+            }
+        }
+        sum.finish(server_key)
+    }
+}
+
+impl EncodedCipherTrait for EncodedCipherOneHot {
+    type ClientKey = ClientKey;
+    type ServerKey = ServerKey;
+
+    fn encrypt(client_key: &ClientKey, c: u8) -> Self {
+        Self::encrypt_over(client_key, c, &(0..=255).collect::<Vec<u8>>())
+    }
+
+    // bytes out of the alphabet decrypt to the lowest of them
+    fn decrypt(self, client_key: &ClientKey) -> u8 {
+        let mut bytes = self.alphabet.iter().zip(self.indicators.iter());
+        match bytes.find(|(_, indicator)| client_key.decrypt(indicator) == 1) {
+            Some((byte, _)) => *byte,
+            None => (0..=255).find(|byte| !self.alphabet.contains(byte)).unwrap_or(0),
+        }
+    }
+
+    fn equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        let bits: Vec<Option<Ciphertext>> =
+            self.alphabet.iter().map(|byte| rhs.indicator(*byte).cloned()).collect();
+        self.sum_and(server_key, &bits)
+    }
+
+    fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        let bits = self.running_sums(server_key, &rhs, true);
+        self.sum_and(server_key, &bits)
     }
 
     fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
-        // (Ai < Bi) + (Ai == Bi) *
-        //          (Aj < Bj) + (Aj == Bj) *
-        //                  (Ak < Bk) + (Ak == Bk) *
-        //                          (Al <= Bl) 
-        let result_i = server_key.unchecked_less(&self.i, &rhs.i);
-        let result_i_equal = server_key.unchecked_equal(&self.i, &rhs.i);
-        let result_j = server_key.unchecked_less(&self.j, &rhs.j);
-        let result_j_equal = server_key.unchecked_equal(&self.j, &rhs.j);
-        let result_k = server_key.unchecked_less(&self.k, &rhs.k);
-        let result_k_equal = server_key.unchecked_equal(&self.k, &rhs.k);
-        let result_l = server_key.unchecked_less_or_equal(&self.l, &rhs.l);
-
-        let result = server_key.unchecked_mul_lsb(&result_k_equal, &result_l);
-        let result = server_key.unchecked_add(&result_k, &result);
-        let result = server_key.unchecked_mul_lsb(&result_j_equal, &result);
-        let result = server_key.unchecked_add(&result_j, &result);
-        let result = server_key.smart_scalar_greater_or_equal(&result, 1_u8);
-        let result = server_key.unchecked_mul_lsb(&result_i_equal, &result);
-        server_key.unchecked_add(&result_i, &result)
-    }
-}
\ No newline at end of file
+        let bits = self.running_sums(server_key, &rhs, false);
+        self.sum_and(server_key, &bits)
+    }
+
+    fn scalar_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        self.sum_where(server_key, |byte| byte == rhs)
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        self.sum_where(server_key, |byte| byte >= rhs)
+    }
+
+    fn scalar_less_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        self.sum_where(server_key, |byte| byte <= rhs)
+    }
+
+    fn in_table(self, server_key: &ServerKey, table: &[bool; 256]) -> Ciphertext {
+        self.sum_where(server_key, |byte| table[byte as usize])
+    }
+
+    // one lookup table per byte of the alphabet, on the sum of its indicator
+    // and of whether it is above the start and below the end
+    fn in_range(self, server_key: &ServerKey, start: Self, end: Self) -> Ciphertext {
+        let above = self.running_sums(server_key, &start, true);
+        let below = self.running_sums(server_key, &end, false);
+        let mut sum = BitSum::new();
+        for (indicator, (above, below)) in self.indicators.iter().zip(above.iter().zip(below.iter())) {
+            if let (Some(above), Some(below)) = (above, below) {
+                let total = server_key.unchecked_add(&server_key.unchecked_add(indicator, above), below);
+                sum.add(server_key, &server_key.smart_scalar_greater_or_equal(&total, 3));
+            }
+        }
+        sum.finish(server_key)
+    }
+}
+
+impl SelectCipherTrait for EncodedCipherOneHot {
+    fn select(self, server_key: &ServerKey, condition: &Ciphertext, replacement: u8) -> Self {
+        let not_condition = server_key.smart_scalar_equal(condition, 0);
+        let indicators = self
+            .alphabet
+            .iter()
+            .zip(self.indicators.iter())
+            .map(|(byte, indicator)| {
+                let kept = and_bits(server_key, indicator, &not_condition);
+                match *byte == replacement {
+                    true => server_key.unchecked_add(&kept, condition),
+                    false => kept,
+                }
+            })
+            .collect();
+        EncodedCipherOneHot {
+            alphabet: self.alphabet,
+            indicators,
+        }
+    }
+}
+
+/// The ID of the class of a byte among the classes a program tells apart, in
+/// a single ciphertext of a message space just large enough for them. Only
+/// comparisons with cleartext IDs and class tables make sense, which are
+/// single lookup tables; comparisons between two ciphertexts need as much
+/// carry space as message space. It doesn't implement `SelectCipherTrait`, a
+/// replacement byte has no class ID, so `replace_all` takes an encoding of the
+/// bytes.
+#[derive(Clone)]
+pub struct EncodedCipherClass {
+    value: Ciphertext,
+}
+
+impl EncodedCipherTrait for EncodedCipherClass {
+    type ClientKey = ClientKey;
+    type ServerKey = ServerKey;
+
+    fn encrypt(client_key: &ClientKey, c: u8) -> Self {
+        EncodedCipherClass {
+            value: client_key.encrypt(c as u64),
+        }
+    }
+
+    fn decrypt(self, client_key: &ClientKey) -> u8 {
+        client_key.decrypt(&self.value) as u8
+    }
+
+    fn equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        server_key.unchecked_equal(&self.value, &rhs.value)
+    }
+
+    fn greater_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        server_key.unchecked_greater_or_equal(&self.value, &rhs.value)
+    }
+
+    fn less_or_equal(self, server_key: &ServerKey, rhs: Self) -> Ciphertext {
+        server_key.unchecked_less_or_equal(&self.value, &rhs.value)
+    }
+
+    fn scalar_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_equal(&self.value, rhs)
+    }
+
+    fn scalar_greater_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_greater_or_equal(&self.value, rhs)
+    }
+
+    fn scalar_less_or_equal(self, server_key: &ServerKey, rhs: u8) -> Ciphertext {
+        server_key.smart_scalar_less_or_equal(&self.value, rhs)
+    }
+
+    // the table is indexed by class ID
+    fn in_table(self, server_key: &ServerKey, table: &[bool; 256]) -> Ciphertext {
+        lookup(server_key, &self.value, |x| table[x as usize] as u64)
+    }
+}
@@ -1,9 +1,12 @@
-use crate::program::{Instruction, Program};
+use crate::dfa::Dfa;
+use crate::nfa::{Nfa, State};
+use crate::program::{group_count, is_word_byte, Instruction, Program};
 
 #[derive(Default, Clone, Debug)]
 struct Context {
     program_counter: usize,
     string_counter: usize,
+    slots: Vec<Option<usize>>,
 }
 
 type Stack = Vec<Context>;
@@ -13,162 +16,313 @@ pub struct Machine {
     string_counter: usize,
     program: Program,
     stack: Stack,
+    // positions recorded by the `Save` instructions
+    slots: Vec<Option<usize>>,
+    // instructions already reached at each position of the input, by index
+    // `position * program.len() + program_counter`
+    visited: Vec<bool>,
 }
 
 impl Machine {
     pub fn new(program: Program) -> Self {
+        let slots = vec![None; 2 * group_count(&program)];
         Self {
             program_counter: 0,
             string_counter: 0,
             program,
             stack: Stack::new(),
+            slots,
+            visited: vec![],
         }
     }
 
     pub fn reset(&mut self) {
+        self.restart(0);
+        self.visited = vec![];
+    }
+
+    // Starts a new match attempt at `start` on the same input, keeping what
+    // the previous attempts visited: what failed from there fails again.
+    fn restart(&mut self, start: usize) {
         self.program_counter = 0;
-        self.string_counter = 0;
+        self.string_counter = start;
         self.stack = Stack::new();
+        self.slots = vec![None; self.slots.len()];
     }
 
+    // Resumes from the last alternative left aside. Returns false when there is
+    // none, meaning the match attempt has failed.
+    fn backtrack(&mut self) -> bool {
+        match self.stack.pop() {
+            Some(context) => {
+                self.program_counter = context.program_counter;
+                self.string_counter = context.string_counter;
+                self.slots = context.slots;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Leaves the current position aside to come back to `program_counter` there
+    // if what follows fails.
+    fn save(&mut self, program_counter: usize) {
+        self.stack.push(Context {
+            program_counter,
+            string_counter: self.string_counter,
+            slots: self.slots.clone(),
+        });
+    }
+
+    /// Searches for the pattern anywhere in the input, as if it was preceded by
+    /// `.*?`: a match is attempted from every position in turn.
     pub fn run(&mut self, input: String) -> bool {
-        let mut state = 0;
-        let mut exact_match = false;
+        self.reset();
+        (0..=input.len()).any(|start| {
+            self.restart(start);
+            self.run_from(input.as_bytes())
+        })
+    }
 
-        while self.program_counter < self.program.len() {
-            if state == self.program.len() {
-                // End of program, return true
-                return true;
+    /// Finds the leftmost match and, among the matches starting there, the
+    /// first one in priority order, like `regex::bytes::Regex::find`: the
+    /// alternatives are tried from left to right, greedy repetitions match as
+    /// much as they can and lazy ones as little. Returns its start and end
+    /// offsets in the input.
+    pub fn find(&mut self, input: String) -> Option<(usize, usize)> {
+        self.captures(input).and_then(|groups| groups[0])
+    }
+
+    /// Spans of the capture groups in the match `find` returns, group 0 being
+    /// the whole match. Groups that took no part in the match are `None`; a
+    /// repeated group keeps its last iteration.
+    pub fn captures(&mut self, input: String) -> Option<Vec<Option<(usize, usize)>>> {
+        self.captures_at(input.as_bytes(), 0)
+    }
+
+    /// Counts the non-overlapping matches like `regex::bytes::Regex::find_iter`:
+    /// each one is the match `find` returns from the end of the previous one,
+    /// except for an empty match right at that end, which is skipped. The
+    /// search goes on one byte further after an empty match, so `a*` matches
+    /// "baa" twice, before the b and over the a's.
+    pub fn count_matches(&mut self, input: String) -> usize {
+        self.find_iter(input.as_bytes()).len()
+    }
+
+    /// Overwrites every byte of the non-overlapping matches, as counted by
+    /// `count_matches`, with the replacement byte.
+    pub fn replace_all(&mut self, input: String, replacement: u8) -> Vec<u8> {
+        let mut output = input.clone().into_bytes();
+        for (start, end) in self.find_iter(input.as_bytes()) {
+            output[start..end].fill(replacement);
+        }
+        output
+    }
+
+    fn find_iter(&mut self, input: &[u8]) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = vec![];
+        let mut from = 0;
+        while from <= input.len() {
+            match self.captures_at(input, from).and_then(|groups| groups[0]) {
+                Some((start, end)) if start == end && spans.last().map(|last| last.1) == Some(end) => {
+                    from = end + 1;
+                }
+                Some((start, end)) => {
+                    spans.push((start, end));
+                    from = if start == end { end + 1 } else { end };
+                }
+                None => break,
             }
+        }
+        spans
+    }
 
-            let current_item = self.program.get(self.program_counter).unwrap();
+    fn captures_at(&mut self, input: &[u8], from: usize) -> Option<Vec<Option<(usize, usize)>>> {
+        self.reset();
+        (from..=input.len()).find_map(|start| {
+            self.restart(start);
+            // the first way of matching from here is the one in priority order
+            if !self.run_from(input) {
+                return None;
+            }
+            let groups = self.slots.chunks(2).skip(1).map(|slot| match (slot[0], slot[1]) {
+                (Some(group_start), Some(group_end)) => Some((group_start, group_end)),
+                _ => None,
+            });
+            Some(std::iter::once(Some((start, self.string_counter))).chain(groups).collect())
+        })
+    }
+
+    // Tries to match the program from the current position of the input.
+    //
+    // An instruction reached again at the same position either failed from
+    // there already, or comes back through a loop iteration that consumed
+    // nothing and would go on forever: it fails without going further. Each
+    // instruction is thus run at most once per position, and the first path
+    // to succeed is the first one in priority order.
+    fn run_from(&mut self, input: &[u8]) -> bool {
+        if self.visited.is_empty() {
+            self.visited = vec![false; self.program.len() * (input.len() + 1)];
+        }
+        while self.program_counter < self.program.len() {
+            let visited = self.string_counter * self.program.len() + self.program_counter;
+            if self.visited[visited] {
+                if !self.backtrack() {
+                    return false;
+                }
+                continue;
+            }
+            self.visited[visited] = true;
+            let current_item = self.program[self.program_counter].clone();
+            let input_char = input.get(self.string_counter).copied();
+            let prev_char = self.string_counter.checked_sub(1).map(|prev| input[prev]);
+            let next_string_counter =
+                (self.string_counter as i32 + current_item.action.offset) as usize;
 
-            match current_item.instruction.clone() {
+            let has_matched = match current_item.instruction {
                 Instruction::Char(c) => {
-                    if self.string_counter >= input.len() {
-                        return false;
-                    }
-                    let input_char = input.as_bytes()[self.string_counter];
-                    let result = input_char == c;
-                    if !result {
-                        if self.stack.is_empty() {
-                            // Failed match, backtrack to previous state
-                            let prev_state = self.program_counter.saturating_sub(1);
-                            let prev_item = self.program[prev_state].clone();
-                            match prev_item.instruction {
-                                Instruction::Jump(_) => {
-                                    return false;
-                                }
-                                _ => {
-                                    state = prev_item.action.next;
-                                    self.string_counter = (self.string_counter as i32
-                                        + prev_item.action.offset)
-                                        as usize;
-                                    self.program_counter = prev_state;
-                                    if exact_match {
-                                        return false;
-                                    }
-                                }
-                            }
-                        } else {
-                            let context = self.stack.pop().unwrap();
-                            self.program_counter = context.program_counter;
-                            self.string_counter = context.string_counter;
-                        }
-                    } else {
-                        // Successful match, advance to next state
-                        state = current_item.action.next;
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                        self.program_counter += 1;
+                    let result = input_char == Some(c);
+                    if result {
+                        self.string_counter = next_string_counter;
                     }
+                    result
                 }
-                Instruction::Match => {
-                    // check qu'on en est à la fin de la string
-                    return self.string_counter == input.len();
-                }
-                Instruction::Start => {
-                    self.program_counter += 1;
-                    exact_match = true;
+                Instruction::Match => self.string_counter == input.len(),
+                Instruction::Start => self.string_counter == 0,
+                Instruction::StartLine => prev_char.unwrap_or(b'\n') == b'\n',
+                Instruction::EndLine => input_char.unwrap_or(b'\n') == b'\n',
+                Instruction::WordBoundary | Instruction::NotWordBoundary => {
+                    let is_word = |byte: Option<u8>| matches!(byte, Some(byte) if is_word_byte(byte));
+                    let is_word_boundary = is_word(prev_char) != is_word(input_char);
+                    is_word_boundary == matches!(current_item.instruction, Instruction::WordBoundary)
                 }
                 Instruction::Repetition(c) => {
-                    let input_char = input.as_bytes()[self.string_counter];
-                    let result = input_char == c;
-                    if result {
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                    } else {
-                        state = current_item.action.next;
-                        self.program_counter += 1;
+                    if input_char == Some(c) {
+                        // greedy: try once more first, stop repeating otherwise
+                        self.save(self.program_counter + 1);
+                        self.string_counter = next_string_counter;
+                        continue;
                     }
+                    true
                 }
                 Instruction::OptionalChar(c) => {
-                    let input_char = input.as_bytes()[self.string_counter];
-                    let result = input_char == c;
-                    if result {
-                        // if it matches we will go next character of the string
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
+                    if input_char == Some(c) {
+                        self.save(self.program_counter + 1);
+                        self.string_counter = next_string_counter;
                     }
-                    // if it doesn't match then we stay at the same sc but fo on pc right to the next state and next instruction
-                    state = current_item.action.next;
-                    self.program_counter += 1;
+                    true
                 }
                 Instruction::IntervalChar(ranges) => {
-                    let mut has_matched = false;
-                    let result = input.as_bytes()[self.string_counter];
-                    for range in ranges.range.iter() {
-                        if range.start() as u8 <= result && result <= range.end() as u8 {
-                            has_matched = true;
-                            break;
-                        }
-                    }
-                    if has_matched {
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                        if !ranges.can_repeat || ranges.is_optional {
-                            state = current_item.action.next;
-                            self.program_counter += 1;
+                    let in_range = match input_char {
+                        Some(c) => ranges
+                            .range
+                            .iter()
+                            .any(|range| range.start <= c && c <= range.end),
+                        None => false,
+                    };
+                    if !in_range {
+                        ranges.is_optional || ranges.can_repeat
+                    } else {
+                        if ranges.is_optional || ranges.can_repeat {
+                            self.save(self.program_counter + 1);
                         }
-                    } else if !has_matched && (ranges.is_optional || ranges.can_repeat) {
-                        state = current_item.action.next;
-                        self.program_counter += 1;
-                    } else if self.stack.is_empty() {
-                        let prev_state = self.program_counter.saturating_sub(1);
-                        let prev_item = self.program[prev_state].clone();
-                        match prev_item.instruction {
-                            Instruction::Jump(_) => {
-                                return false;
-                            }
-                            _ => {
-                                state = prev_item.action.next;
-                                self.string_counter =
-                                    (self.string_counter as i32 + prev_item.action.offset) as usize;
-                                self.program_counter = prev_state;
-                                if exact_match {
-                                    return false;
-                                }
-                            }
+                        self.string_counter = next_string_counter;
+                        if ranges.can_repeat && !ranges.is_optional {
+                            continue;
                         }
-                    } else {
-                        let context = self.stack.pop().unwrap();
-                        self.program_counter = context.program_counter;
-                        self.string_counter = context.string_counter;
+                        true
                     }
                 }
                 Instruction::Branch(pc) => {
-                    let context = Context {
-                        program_counter: pc,
-                        string_counter: self.string_counter,
-                    };
-                    self.stack.push(context);
-                    self.program_counter += 1;
+                    self.save(pc);
+                    true
                 }
                 Instruction::Jump(pc) => {
                     self.program_counter = pc;
+                    continue;
+                }
+                Instruction::Save(slot) => {
+                    self.slots[slot] = Some(self.string_counter);
+                    true
                 }
+                Instruction::Accept(_) => return true,
+            };
+
+            if has_matched {
+                self.program_counter += 1;
+            } else if !self.backtrack() {
+                return false;
             }
         }
         true
     }
 }
+
+pub struct NfaMachine {
+    nfa: Nfa,
+}
+
+impl NfaMachine {
+    pub fn new(nfa: Nfa) -> Self {
+        Self { nfa }
+    }
+
+    pub fn run(&self, input: String) -> bool {
+        let input = input.as_bytes();
+        let mut targets = vec![false; self.nfa.states.len()];
+
+        for position in 0..=input.len() {
+            let at_start = position == 0;
+            let at_end = position == input.len();
+
+            // the pattern may start matching at any position
+            targets[self.nfa.start] = true;
+            let mut active = vec![false; self.nfa.states.len()];
+            for (target, _) in targets.iter().enumerate().filter(|(_, is_set)| **is_set) {
+                for state in self.nfa.closure(target, at_start, at_end) {
+                    active[*state] = true;
+                }
+            }
+
+            targets = vec![false; self.nfa.states.len()];
+            for (state, _) in active.iter().enumerate().filter(|(_, is_set)| **is_set) {
+                match &self.nfa.states[state] {
+                    State::Match => return true,
+                    State::Char { byte, next } => {
+                        if !at_end && input[position] == *byte {
+                            targets[*next] = true;
+                        }
+                    }
+                    State::Class { ranges, next } => {
+                        if !at_end
+                            && ranges.iter().any(|range| {
+                                range.start <= input[position] && input[position] <= range.end
+                            })
+                        {
+                            targets[*next] = true;
+                        }
+                    }
+                    State::Split(_) | State::Look { .. } => {}
+                }
+            }
+        }
+        false
+    }
+}
+
+pub struct DfaMachine {
+    dfa: Dfa,
+}
+
+impl DfaMachine {
+    pub fn new(dfa: Dfa) -> Self {
+        Self { dfa }
+    }
+
+    pub fn run(&self, input: String) -> bool {
+        let state = input.bytes().fold(self.dfa.start, |state, byte| {
+            self.dfa.transitions[state][self.dfa.class_of(byte)]
+        });
+        self.dfa.accepting[state]
+    }
+}
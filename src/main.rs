@@ -2,7 +2,9 @@ use tfhe::shortint::prelude::*;
 use tfhe_regex::{EncodedCipher4bits, EncodedCipherTrait};
 
 pub mod compiler;
+pub mod dfa;
 pub mod machine;
+pub mod nfa;
 pub mod program;
 pub mod tfhe_machine;
 
@@ -31,7 +33,7 @@ fn main() {
     let checker = CheckerCipher {
         client_key: client_key.clone(),
     };
-    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$");
+    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
     let program = program::cipher_program(&client_key, program);
 
     let input: Vec<EncodedCipher4bits> = "helllllllobc"
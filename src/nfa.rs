@@ -0,0 +1,146 @@
+use tfhe_regex::EncodedCipherTrait;
+
+use crate::program::CiphertextRange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Look {
+    Start, // Anchor start
+    End,   // Anchor end
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ByteRange {
+    pub start: u8,
+    pub end: u8,
+}
+
+#[derive(Debug, Clone)]
+pub enum State {
+    Char { byte: u8, next: usize },
+    Class { ranges: Vec<ByteRange>, next: usize },
+    Split(Vec<usize>), // epsilon transitions to every target
+    Look { look: Look, next: usize },
+    Match,
+}
+
+#[derive(Clone)]
+pub enum CipherState<T: EncodedCipherTrait + Clone> {
+    CipherChar { ct: T, next: usize },
+    CipherClass { ranges: Vec<CiphertextRange<T>>, next: usize },
+    Split(Vec<usize>),
+    Look { look: Look, next: usize },
+    Match,
+}
+
+// For every state and every combination of (at start, at end) of the input,
+// the consuming states and match states reachable through epsilon transitions.
+type Closures = Vec<[Vec<usize>; 4]>;
+
+fn context(at_start: bool, at_end: bool) -> usize {
+    (at_start as usize) | ((at_end as usize) << 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct Nfa {
+    pub states: Vec<State>,
+    pub start: usize,
+    closures: Closures,
+}
+
+impl Nfa {
+    pub fn new(states: Vec<State>, start: usize) -> Self {
+        let closures = (0..states.len())
+            .map(|state| {
+                [
+                    epsilon_closure(&states, state, false, false),
+                    epsilon_closure(&states, state, true, false),
+                    epsilon_closure(&states, state, false, true),
+                    epsilon_closure(&states, state, true, true),
+                ]
+            })
+            .collect();
+        Self {
+            states,
+            start,
+            closures,
+        }
+    }
+
+    pub fn closure(&self, state: usize, at_start: bool, at_end: bool) -> &[usize] {
+        &self.closures[state][context(at_start, at_end)]
+    }
+}
+
+#[derive(Clone)]
+pub struct CipherNfa<T: EncodedCipherTrait + Clone> {
+    pub states: Vec<CipherState<T>>,
+    pub start: usize,
+    closures: Closures,
+}
+
+impl<T: EncodedCipherTrait + Clone> CipherNfa<T> {
+    pub fn closure(&self, state: usize, at_start: bool, at_end: bool) -> &[usize] {
+        &self.closures[state][context(at_start, at_end)]
+    }
+}
+
+fn epsilon_closure(states: &[State], state: usize, at_start: bool, at_end: bool) -> Vec<usize> {
+    let mut closure = vec![];
+    let mut visited = vec![false; states.len()];
+    let mut todo = vec![state];
+    while let Some(state) = todo.pop() {
+        if visited[state] {
+            continue;
+        }
+        visited[state] = true;
+        match &states[state] {
+            State::Char { .. } | State::Class { .. } | State::Match => closure.push(state),
+            State::Split(targets) => todo.extend(targets.iter().rev()),
+            State::Look { look, next } => {
+                let holds = match look {
+                    Look::Start => at_start,
+                    Look::End => at_end,
+                };
+                if holds {
+                    todo.push(*next);
+                }
+            }
+        }
+    }
+    closure.sort_unstable();
+    closure
+}
+
+fn cipher_state<T: EncodedCipherTrait + Clone>(client_key: &T::ClientKey, state: &State) -> CipherState<T> {
+    match state.clone() {
+        State::Char { byte, next } => CipherState::CipherChar {
+            ct: T::encrypt(client_key, byte),
+            next,
+        },
+        State::Class { ranges, next } => CipherState::CipherClass {
+            ranges: ranges
+                .iter()
+                .map(|range| CiphertextRange {
+                    start: T::encrypt(client_key, range.start),
+                    end: T::encrypt(client_key, range.end),
+                })
+                .collect(),
+            next,
+        },
+        State::Split(targets) => CipherState::Split(targets),
+        State::Look { look, next } => CipherState::Look { look, next },
+        State::Match => CipherState::Match,
+    }
+}
+
+pub fn cipher_nfa<T: EncodedCipherTrait + Clone>(client_key: &T::ClientKey, nfa: Nfa) -> CipherNfa<T> {
+    CipherNfa {
+        states: nfa
+            .states
+            .iter()
+            .map(|state| cipher_state(client_key, state))
+            .collect(),
+        start: nfa.start,
+        closures: nfa.closures,
+    }
+}
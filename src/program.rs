@@ -1,24 +1,65 @@
-use regex_syntax::hir::ClassUnicodeRange;
-use tfhe::shortint::ClientKey;
 use tfhe_regex::EncodedCipherTrait;
 
+use crate::nfa::ByteRange;
+
 #[derive(Debug, Clone)]
 pub struct IntervalCharOptions {
-    pub range: Vec<ClassUnicodeRange>,
+    pub range: Vec<ByteRange>,
     pub can_repeat: bool,
     pub is_optional: bool,
 }
 
+// Truth table of a set of bytes, indexed by byte.
+pub type ByteTable = [bool; 256];
+
+fn byte_table(ranges: impl Iterator<Item = (u8, u8)>) -> ByteTable {
+    let mut table = [false; 256];
+    for (start, end) in ranges {
+        for byte in start..=end {
+            table[byte as usize] = true;
+        }
+    }
+    table
+}
+
 #[derive(Debug, Clone)]
 pub enum Instruction {
     Char(u8),
     Match,                 // Anchor end
     Start,                 // Anchor start
+    StartLine,             // Anchor start of line, in multiline mode
+    EndLine,               // Anchor end of line, in multiline mode
+    WordBoundary,          // \b, on ASCII word bytes
+    NotWordBoundary,       // \B, on ASCII word bytes
     Repetition(u8),   // 0 to infinite repetition of a character
     OptionalChar(u8), // in case of bounded repetitions or ZeroOrOneRepetition
     IntervalChar(IntervalCharOptions),
     Branch(usize), // context to fallback
     Jump(usize),
+    Save(usize), // records the position in a capture slot
+    Accept(usize), // pattern of a set that matched, see `CompiledSet`
+}
+
+// Bytes of the \w class, the only ones the word boundary assertions look at.
+pub const WORD_RANGES: [(u8, u8); 4] = [(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')];
+
+// Capture group `index` starts at slot `2 * index` and ends at slot `2 * index + 1`,
+// group 0 being the whole match.
+pub fn group_count(program: &[ProgramItem]) -> usize {
+    program
+        .iter()
+        .filter_map(|item| match item.instruction {
+            Instruction::Save(slot) => Some(slot / 2 + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(1)
+}
+
+pub fn is_word_byte(byte: u8) -> bool {
+    WORD_RANGES
+        .iter()
+        .any(|(start, end)| *start <= byte && byte <= *end)
 }
 
 #[derive(Clone)]
@@ -39,11 +80,17 @@ pub enum CipherInstruction<T:EncodedCipherTrait+Clone> {
     CipherChar(T),
     Match, // Anchor end
     Start, // Anchor start
+    CipherStartLine(T), // encrypted newline
+    CipherEndLine(T),   // encrypted newline
+    CipherWordBoundary(Vec<CiphertextRange<T>>),    // encrypted \w ranges
+    CipherNotWordBoundary(Vec<CiphertextRange<T>>), // encrypted \w ranges
     CipherRepetition(T),
     CipherOptionalChar(T),
     CipherIntervalChar(CipherIntervalCharOptions<T>),
     Branch(usize), // context to fallback
     Jump(usize),
+    Save(usize), // records the position in a capture slot
+    Accept(usize), // pattern of a set that matched
 }
 
 #[derive(Default, Clone, Debug)]
@@ -68,7 +115,17 @@ pub struct CipherProgramItem<T:EncodedCipherTrait+Clone> {
 
 pub type CipherProgram<T> = Vec<CipherProgramItem<T>>;
 
-fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, program_item: &ProgramItem) -> CipherProgramItem<T> {
+fn cipher_word_ranges<T:EncodedCipherTrait+Clone>(client_key: &T::ClientKey) -> Vec<CiphertextRange<T>> {
+    WORD_RANGES
+        .iter()
+        .map(|(start, end)| CiphertextRange {
+            start: T::encrypt(client_key, *start),
+            end: T::encrypt(client_key, *end),
+        })
+        .collect()
+}
+
+fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &T::ClientKey, program_item: &ProgramItem) -> CipherProgramItem<T> {
     let instruction: CipherInstruction<T> = match program_item.instruction.clone() {
         Instruction::Char(c) => {
             let ct = T::encrypt(client_key, c);
@@ -76,6 +133,12 @@ fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, progr
         }
         Instruction::Match => CipherInstruction::Match,
         Instruction::Start => CipherInstruction::Start,
+        Instruction::StartLine => CipherInstruction::CipherStartLine(T::encrypt(client_key, b'\n')),
+        Instruction::EndLine => CipherInstruction::CipherEndLine(T::encrypt(client_key, b'\n')),
+        Instruction::WordBoundary => CipherInstruction::CipherWordBoundary(cipher_word_ranges(client_key)),
+        Instruction::NotWordBoundary => {
+            CipherInstruction::CipherNotWordBoundary(cipher_word_ranges(client_key))
+        }
         Instruction::Repetition(c) => {
             let ct = T::encrypt(client_key, c);
             CipherInstruction::CipherRepetition(ct)
@@ -89,10 +152,8 @@ fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, progr
                 .range
                 .iter()
                 .map(|range| {
-                    let start = range.start() as u8;
-                    let start_ct = T::encrypt(client_key, start);
-                    let end = range.end() as u8;
-                    let end_ct = T::encrypt(client_key, end);
+                    let start_ct = T::encrypt(client_key, range.start);
+                    let end_ct = T::encrypt(client_key, range.end);
                     CiphertextRange {
                         start: start_ct,
                         end: end_ct,
@@ -107,6 +168,8 @@ fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, progr
         }
         Instruction::Branch(pc) => CipherInstruction::Branch(pc),
         Instruction::Jump(pc) => CipherInstruction::Jump(pc),
+        Instruction::Save(slot) => CipherInstruction::Save(slot),
+        Instruction::Accept(index) => CipherInstruction::Accept(index),
     };
     CipherProgramItem {
         instruction,
@@ -114,10 +177,236 @@ fn cipher_program_item<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, progr
     }
 }
 
-pub fn cipher_program<T:EncodedCipherTrait+Clone>(client_key: &ClientKey, program: Program) -> CipherProgram<T> {
+pub fn cipher_program<T:EncodedCipherTrait+Clone>(client_key: &T::ClientKey, program: Program) -> CipherProgram<T> {
     let cipher_program = program
         .iter()
         .map(|program_item| cipher_program_item(client_key, program_item))
         .collect();
     cipher_program
 }
+
+/// Instruction of a program whose characters are not encrypted, along with
+/// the truth table of the bytes it tests the input against when it tests a
+/// class or a word boundary. The tables let the machine evaluate them with a
+/// few lookup tables on the limbs of the input rather than comparisons. In a
+/// program over byte classes, see `ByteClasses`, the line anchors also have
+/// the table of the class of the newline.
+#[derive(Debug, Clone)]
+pub struct ClearProgramItem {
+    pub instruction: Instruction,
+    pub action: Action,
+    pub table: Option<ByteTable>,
+}
+
+pub type ClearProgram = Vec<ClearProgramItem>;
+
+pub fn clear_program(program: Program) -> ClearProgram {
+    program
+        .into_iter()
+        .map(|item| {
+            let table = match &item.instruction {
+                Instruction::IntervalChar(ranges) => Some(byte_table(
+                    ranges.range.iter().map(|range| (range.start, range.end)),
+                )),
+                Instruction::WordBoundary | Instruction::NotWordBoundary => {
+                    Some(byte_table(WORD_RANGES.iter().copied()))
+                }
+                _ => None,
+            };
+            ClearProgramItem {
+                instruction: item.instruction,
+                action: item.action,
+                table,
+            }
+        })
+        .collect()
+}
+
+/// Several patterns compiled into one program, built by
+/// `Compiler::compile_set`. The programs share the states of their common
+/// prefix, and each of them ends with an `Accept` instruction instead of
+/// running past the end of the program.
+#[derive(Debug, Clone)]
+pub struct CompiledSet {
+    pub program: Program,
+    // `Accept` instruction of each pattern
+    pub accepts: Vec<usize>,
+    // character test performed by each instruction, equal tests sharing the
+    // same index so that they can be evaluated once per input character
+    pub comparisons: Vec<Option<usize>>,
+}
+
+// Bytes a consuming instruction tests the input against.
+fn comparison_ranges(instruction: &Instruction) -> Option<Vec<(u8, u8)>> {
+    match instruction {
+        Instruction::Char(c) | Instruction::Repetition(c) | Instruction::OptionalChar(c) => {
+            Some(vec![(*c, *c)])
+        }
+        Instruction::IntervalChar(ranges) => {
+            Some(ranges.range.iter().map(|range| (range.start, range.end)).collect())
+        }
+        _ => None,
+    }
+}
+
+impl CompiledSet {
+    pub fn new(program: Program) -> Self {
+        let mut accepts: Vec<(usize, usize)> = program
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, item)| match item.instruction {
+                Instruction::Accept(index) => Some((index, pc)),
+                _ => None,
+            })
+            .collect();
+        accepts.sort_unstable();
+
+        let mut tests: Vec<Vec<(u8, u8)>> = vec![];
+        let comparisons = program
+            .iter()
+            .map(|item| {
+                let ranges = comparison_ranges(&item.instruction)?;
+                Some(match tests.iter().position(|test| *test == ranges) {
+                    Some(index) => index,
+                    None => {
+                        tests.push(ranges);
+                        tests.len() - 1
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            program,
+            accepts: accepts.into_iter().map(|(_, pc)| pc).collect(),
+            comparisons,
+        }
+    }
+}
+
+/// The bytes grouped into the classes that no test of a program can tell
+/// apart, the way regex engines build byte classes. Matching input encrypted
+/// as class IDs, which fit in a few bits of message, only takes lookup tables
+/// on that small message space.
+#[derive(Debug, Clone)]
+pub struct ByteClasses {
+    // class ID of every byte, the client maps its input through it
+    pub map: [u8; 256],
+    pub count: usize,
+}
+
+impl ByteClasses {
+    /// The fewest classes for the program: bytes go together when every
+    /// character test, class, newline and word byte test gives the same
+    /// answer for them. Classes are numbered by their lowest byte.
+    pub fn new(program: &[ProgramItem]) -> Self {
+        let mut tests: Vec<ByteTable> = vec![];
+        for item in program.iter() {
+            let table = match &item.instruction {
+                Instruction::StartLine | Instruction::EndLine => byte_table([(b'\n', b'\n')].into_iter()),
+                Instruction::WordBoundary | Instruction::NotWordBoundary => {
+                    byte_table(WORD_RANGES.iter().copied())
+                }
+                instruction => match comparison_ranges(instruction) {
+                    Some(ranges) => byte_table(ranges.into_iter()),
+                    None => continue,
+                },
+            };
+            if !tests.contains(&table) {
+                tests.push(table);
+            }
+        }
+
+        let mut signatures: Vec<Vec<bool>> = vec![];
+        let mut map = [0; 256];
+        for byte in 0..=255_u8 {
+            let signature: Vec<bool> = tests.iter().map(|table| table[byte as usize]).collect();
+            map[byte as usize] = match signatures.iter().position(|other| *other == signature) {
+                Some(class) => class as u8,
+                None => {
+                    signatures.push(signature);
+                    (signatures.len() - 1) as u8
+                }
+            };
+        }
+        Self {
+            map,
+            count: signatures.len(),
+        }
+    }
+
+    /// Bits of message the class IDs need.
+    pub fn bits(&self) -> u32 {
+        (usize::BITS - (self.count - 1).leading_zeros()).max(1)
+    }
+
+    // table over the class IDs of a table over the bytes
+    fn class_table(&self, table: &ByteTable) -> ByteTable {
+        let mut class_table = [false; 256];
+        for byte in 0..256 {
+            class_table[self.map[byte] as usize] = table[byte];
+        }
+        class_table
+    }
+
+    /// The program as a `ClearProgram` testing class IDs instead of bytes, for
+    /// input encrypted with `convert_str_to_class_cts`.
+    pub fn clear_program(&self, program: Program) -> ClearProgram {
+        let newline = byte_table([(b'\n', b'\n')].into_iter());
+        clear_program(program)
+            .into_iter()
+            .map(|item| {
+                let class_of = |c: u8| self.map[c as usize];
+                let (instruction, table) = match item.instruction {
+                    Instruction::Char(c) => (Instruction::Char(class_of(c)), None),
+                    Instruction::OptionalChar(c) => (Instruction::OptionalChar(class_of(c)), None),
+                    Instruction::Repetition(c) => (Instruction::Repetition(class_of(c)), None),
+                    Instruction::IntervalChar(ranges) => {
+                        let table = self.class_table(&item.table.unwrap());
+                        let ranges = IntervalCharOptions {
+                            range: table_ranges(&table),
+                            ..ranges
+                        };
+                        (Instruction::IntervalChar(ranges), Some(table))
+                    }
+                    Instruction::StartLine | Instruction::EndLine => {
+                        (item.instruction, Some(self.class_table(&newline)))
+                    }
+                    instruction => (instruction, item.table.map(|table| self.class_table(&table))),
+                };
+                ClearProgramItem {
+                    instruction,
+                    action: item.action,
+                    table,
+                }
+            })
+            .collect()
+    }
+}
+
+// Runs of consecutive set entries of a table.
+fn table_ranges(table: &ByteTable) -> Vec<ByteRange> {
+    let mut ranges: Vec<ByteRange> = vec![];
+    for byte in (0..=255_u8).filter(|byte| table[*byte as usize]) {
+        match ranges.last_mut() {
+            Some(range) if range.end as usize + 1 == byte as usize => range.end = byte,
+            _ => ranges.push(ByteRange { start: byte, end: byte }),
+        }
+    }
+    ranges
+}
+
+#[derive(Clone)]
+pub struct CipherCompiledSet<T: EncodedCipherTrait + Clone> {
+    pub program: CipherProgram<T>,
+    pub accepts: Vec<usize>,
+    pub comparisons: Vec<Option<usize>>,
+}
+
+pub fn cipher_set<T: EncodedCipherTrait + Clone>(client_key: &T::ClientKey, set: CompiledSet) -> CipherCompiledSet<T> {
+    CipherCompiledSet {
+        program: cipher_program(client_key, set.program),
+        accepts: set.accepts,
+        comparisons: set.comparisons,
+    }
+}
@@ -1,9 +1,10 @@
-use crate::compiler::Compiler;
-use crate::machine::Machine;
+use crate::compiler::{CompileError, Compiler, Construct};
+use crate::machine::{DfaMachine, Machine, NfaMachine};
+use crate::program::{ByteClasses, Instruction};
 
 #[test]
 fn simple_string() {
-    let program = Compiler::compile(r"abc");
+    let program = Compiler::compile(r"abc").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc".to_string()));
     assert!(machine.run("123abc".to_string()));
@@ -13,231 +14,231 @@ fn simple_string() {
 
 #[test]
 fn simple_string_end_matching_should_succeed() {
-    let program = Compiler::compile(r"abc$");
+    let program = Compiler::compile(r"abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("123abc".to_string()));
 }
 
 #[test]
 fn simple_string_end_matching_should_fail() {
-    let program = Compiler::compile(r"abc$");
+    let program = Compiler::compile(r"abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("123abc456".to_string()));
 }
 
 #[test]
 fn simple_string_start_matching_should_succeed() {
-    let program = Compiler::compile(r"^abc");
+    let program = Compiler::compile(r"^abc").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc123".to_string()));
 }
 
 #[test]
 fn simple_string_start_matching_should_fail() {
-    let program = Compiler::compile(r"^abc");
+    let program = Compiler::compile(r"^abc").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("123abc".to_string()));
 }
 
 #[test]
 fn simple_string_exact_matching_should_succeed() {
-    let program = Compiler::compile(r"^abc$");
+    let program = Compiler::compile(r"^abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc".to_string()));
 }
 
 #[test]
 fn simple_string_exact_matching_should_fail() {
-    let program = Compiler::compile(r"^abc$");
+    let program = Compiler::compile(r"^abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("aabc".to_string()));
 }
 
 #[test]
 fn simple_string_exact_matching_should_fail_2() {
-    let program = Compiler::compile(r"^abc$");
+    let program = Compiler::compile(r"^abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abccc".to_string()));
 }
 
 #[test]
 fn simple_string_one_or_more_matching_should_succeed() {
-    let program = Compiler::compile(r"^ab+c$");
+    let program = Compiler::compile(r"^ab+c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbc".to_string()));
 }
 
 #[test]
 fn simple_string_one_or_more_matching_should_succeed_2() {
-    let program = Compiler::compile(r"^ab+c$");
+    let program = Compiler::compile(r"^ab+c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc".to_string()));
 }
 
 #[test]
 fn simple_string_one_or_more_matching_should_fail() {
-    let program = Compiler::compile(r"^ab+c$");
+    let program = Compiler::compile(r"^ab+c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("ac".to_string()));
 }
 
 #[test]
 fn simple_string_zero_or_more_matching_should_succeed() {
-    let program = Compiler::compile(r"^ab*c$");
+    let program = Compiler::compile(r"^ab*c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("ac".to_string()));
 }
 
 #[test]
 fn simple_string_zero_or_more_matching_should_succeed_2() {
-    let program = Compiler::compile(r"^ab*c$");
+    let program = Compiler::compile(r"^ab*c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbbc".to_string()));
 }
 
 #[test]
 fn simple_string_optional_matching_should_succeed() {
-    let program = Compiler::compile(r"^ab?c$");
+    let program = Compiler::compile(r"^ab?c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc".to_string()));
 }
 
 #[test]
 fn simple_string_optional_matching_should_succeed_2() {
-    let program = Compiler::compile(r"^ab?c$");
+    let program = Compiler::compile(r"^ab?c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("ac".to_string()));
 }
 
 #[test]
 fn simple_string_optional_matching_should_fail() {
-    let program = Compiler::compile(r"^ab?c$");
+    let program = Compiler::compile(r"^ab?c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_succeed() {
-    let program = Compiler::compile(r"^ab{2}c$");
+    let program = Compiler::compile(r"^ab{2}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_fail() {
-    let program = Compiler::compile(r"^ab{2}c$");
+    let program = Compiler::compile(r"^ab{2}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abbbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_fail_2() {
-    let program = Compiler::compile(r"^ab{2}c$");
+    let program = Compiler::compile(r"^ab{2}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_succeed_2() {
-    let program = Compiler::compile(r"^ab{3,}c$");
+    let program = Compiler::compile(r"^ab{3,}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_succeed_3() {
-    let program = Compiler::compile(r"^ab{3,}c$");
+    let program = Compiler::compile(r"^ab{3,}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbbbbbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_fail_3() {
-    let program = Compiler::compile(r"^ab{3,}c$");
+    let program = Compiler::compile(r"^ab{3,}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_succeed_4() {
-    let program = Compiler::compile(r"^ab{2,4}c$");
+    let program = Compiler::compile(r"^ab{2,4}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abbbbc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_fail_4() {
-    let program = Compiler::compile(r"^ab{2,4}c$");
+    let program = Compiler::compile(r"^ab{2,4}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abc".to_string()));
 }
 
 #[test]
 fn simple_string_numbered_matching_should_fail_5() {
-    let program = Compiler::compile(r"^ab{2,4}c$");
+    let program = Compiler::compile(r"^ab{2,4}c$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abbbbbc".to_string()));
 }
 
 #[test]
 fn escaping_special_characters_should_succeed() {
-    let program = Compiler::compile(r"^\.$");
+    let program = Compiler::compile(r"^\.$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run(".".to_string()));
 }
 
 #[test]
 fn escaping_special_characters_should_succeed_2() {
-    let program = Compiler::compile(r"^\*$");
+    let program = Compiler::compile(r"^\*$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("*".to_string()));
 }
 
 #[test]
 fn character_range_matching_should_succeed() {
-    let program = Compiler::compile(r"^[abc]$");
+    let program = Compiler::compile(r"^[abc]$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("a".to_string()));
 }
 
 #[test]
 fn character_range_matching_should_fail() {
-    let program = Compiler::compile(r"^[abc]$");
+    let program = Compiler::compile(r"^[abc]$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("d".to_string()));
 }
 
 #[test]
 fn character_range_not_matching_should_succeed() {
-    let program = Compiler::compile(r"^[^ade]$");
+    let program = Compiler::compile(r"^[^ade]$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("b".to_string()));
 }
 
 #[test]
 fn character_range_not_matching_should_fail() {
-    let program = Compiler::compile(r"^[^ade]$");
+    let program = Compiler::compile(r"^[^ade]$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("a".to_string()));
 }
 
 #[test]
 fn any_character_matching_should_succeed() {
-    let program = Compiler::compile(r"^.$");
+    let program = Compiler::compile(r"^.$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("A".to_string()));
 }
 
 #[test]
 fn case_insensitive_argument_should_succeed() {
-    let program = Compiler::compile(r"(?i)^abc$");
+    let program = Compiler::compile(r"(?i)^abc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("ABC".to_string()));
 }
 
 #[test]
 fn alternation_should_succeed() {
-    let program = Compiler::compile(r"0a|bcd$");
+    let program = Compiler::compile(r"0a|bcd$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("0a".to_string()));
     machine.reset();
@@ -246,7 +247,7 @@ fn alternation_should_succeed() {
 
 #[test]
 fn alternation_should_succeed_2() {
-    let program = Compiler::compile(r"a(bc|ed)42$");
+    let program = Compiler::compile(r"a(bc|ed)42$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("abc42".to_string()));
     machine.reset();
@@ -255,7 +256,7 @@ fn alternation_should_succeed_2() {
 
 #[test]
 fn alternation_should_fail() {
-    let program = Compiler::compile(r"0a|bcd$");
+    let program = Compiler::compile(r"0a|bcd$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("0b".to_string()));
     machine.reset();
@@ -264,7 +265,7 @@ fn alternation_should_fail() {
 
 #[test]
 fn alternation_should_fail_2() {
-    let program = Compiler::compile(r"a(bc|ed)42$");
+    let program = Compiler::compile(r"a(bc|ed)42$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("abd42".to_string()));
     machine.reset();
@@ -273,7 +274,7 @@ fn alternation_should_fail_2() {
 
 #[test]
 fn alternation_string_numbered_matching_should_succeed() {
-    let program = Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$");
+    let program = Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("helabbbc".to_string()));
     machine.reset();
@@ -282,7 +283,7 @@ fn alternation_string_numbered_matching_should_succeed() {
 
 #[test]
 fn alternation_string_numbered_matching_should_fail() {
-    let program = Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$");
+    let program = Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("helabbc".to_string()));
     machine.reset();
@@ -291,7 +292,7 @@ fn alternation_string_numbered_matching_should_fail() {
 
 #[test]
 fn repetition_with_range_should_succeed() {
-    let program = Compiler::compile(r"^01[b-e]{4}56$");
+    let program = Compiler::compile(r"^01[b-e]{4}56$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("01bbbb56".to_string()));
     machine.reset();
@@ -300,7 +301,7 @@ fn repetition_with_range_should_succeed() {
 
 #[test]
 fn repetition_with_range_should_fail() {
-    let program = Compiler::compile(r"^01[b-e]{4}56$");
+    let program = Compiler::compile(r"^01[b-e]{4}56$").unwrap();
     let mut machine = Machine::new(program);
     assert!(!machine.run("01bb56".to_string()));
     machine.reset();
@@ -309,9 +310,689 @@ fn repetition_with_range_should_fail() {
 
 #[test]
 fn repetition_with_range_should_succeed_1() {
-    let program = Compiler::compile(r"^hel(a[b-e]{2}|[l-n]{3,}o)bc$");
+    let program = Compiler::compile(r"^hel(a[b-e]{2}|[l-n]{3,}o)bc$").unwrap();
     let mut machine = Machine::new(program);
     assert!(machine.run("helacdbc".to_string()));
     machine.reset();
     assert!(machine.run("hellllobc".to_string()));
 }
+
+#[test]
+fn nfa_agrees_with_backtracking_machine() {
+    for (pattern, inputs) in [
+        (r"abc", vec!["abc", "123abc", "abc123", "123abc456", "ab"]),
+        (r"abc$", vec!["123abc", "123abc456"]),
+        (r"^abc", vec!["abc123", "123abc"]),
+        (r"^abc$", vec!["abc", "aabc", "abccc"]),
+        (r"^ab+c$", vec!["abbc", "abc", "ac"]),
+        (r"^ab*c$", vec!["ac", "abbbc"]),
+        (r"^ab?c$", vec!["abc", "ac", "abbc"]),
+        (r"^ab{2}c$", vec!["abbc", "abbbc", "abc"]),
+        (r"^ab{3,}c$", vec!["abbbc", "abbbbbbc", "abbc"]),
+        (r"^ab{2,4}c$", vec!["abbbbc", "abc", "abbbbbc"]),
+        (r"^[^ade]$", vec!["b", "a"]),
+        (r"(?i)^abc$", vec!["ABC", "aBc", "abd"]),
+        (r"0a|bcd$", vec!["0a", "bcd", "0b", "bce"]),
+        (r"a(bc|ed)42$", vec!["abc42", "aed42", "abd42", "abed42"]),
+        (r"^hel(ab{2}|l{3,}o)bc$", vec!["helabbbc", "helllllllobc", "helabbc", "helllobc"]),
+        (r"^01[b-e]{4}56$", vec!["01bbbb56", "01bcde56", "01bb56", "01bcfg56"]),
+        (r"(?-u)^a[\x80-\xff]{2}b$", vec!["a\u{e9}b", "ab", "axyb"]),
+        (r"(?-u)^[\xc0-\xdf][\x80-\xbf]*$", vec!["\u{e9}", "e\u{e9}", ""]),
+        (r"(?-u)^x[\x80-\xff]?y$", vec!["xy", "x\u{80}y", "x\u{e9}y"]),
+        (r"^caf\x{e9}$", vec!["caf\u{e9}", "cafe", "caf\u{e8}"]),
+        (r"^[\x{3b1}-\x{3c9}]{2}$", vec!["\u{3b1}\u{3c9}", "\u{3b1}", "\u{391}\u{3b1}"]),
+        (r"^.$", vec!["\u{1f600}", "\u{e9}", "ab"]),
+    ] {
+        let nfa_machine = NfaMachine::new(Compiler::compile_nfa(pattern).unwrap());
+        for input in inputs {
+            let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+            assert_eq!(
+                nfa_machine.run(input.to_string()),
+                machine.run(input.to_string()),
+                "{} against {}",
+                pattern,
+                input
+            );
+        }
+    }
+}
+
+#[test]
+fn nfa_repetition_of_group_should_succeed() {
+    let machine = NfaMachine::new(Compiler::compile_nfa(r"^(ab)+(c|de){1,2}$").unwrap());
+    assert!(machine.run("abc".to_string()));
+    assert!(machine.run("ababdec".to_string()));
+    assert!(!machine.run("abcdec".to_string()));
+    assert!(!machine.run("aabc".to_string()));
+}
+
+#[test]
+fn nfa_empty_input() {
+    let machine = NfaMachine::new(Compiler::compile_nfa(r"^a*$").unwrap());
+    assert!(machine.run("".to_string()));
+    let machine = NfaMachine::new(Compiler::compile_nfa(r"a").unwrap());
+    assert!(!machine.run("".to_string()));
+}
+
+#[test]
+fn dfa_agrees_with_nfa() {
+    for (pattern, inputs) in [
+        (r"abc", vec!["abc", "123abc456", "ab", "aabc", ""]),
+        (r"^abc$", vec!["abc", "aabc", "abccc"]),
+        (r"^ab{2,4}c$", vec!["abbbbc", "abc", "abbbbbc"]),
+        (r"^[^ade]$", vec!["b", "a", ""]),
+        (r"a(bc|ed)42$", vec!["abc42", "aed42", "abd42", "abed42"]),
+        (r"^hel(a[b-e]{2}|[l-n]{3,}o)bc$", vec!["helacdbc", "hellllobc", "helaxdbc"]),
+        (r"(?-u)[\x80-\xff]+z", vec!["a\u{e9}z", "az", "\u{e9}"]),
+        (r"[\x{3b1}-\x{3c9}]+s", vec!["\u{3bb}s", "\u{391}s", "s"]),
+        (r"^[x\x{e9}\x{20ac}\x{1f600}]$", vec!["\u{20ac}", "\u{1f600}", "\u{e9}\u{e9}", ""]),
+        (r"^(ab)+(c|de){1,2}$", vec!["abc", "ababdec", "abcdec", "aabc"]),
+        (r"x*", vec!["", "y"]),
+    ] {
+        let nfa_machine = NfaMachine::new(Compiler::compile_nfa(pattern).unwrap());
+        let dfa_machine = DfaMachine::new(Compiler::compile_dfa(pattern).unwrap());
+        for input in inputs {
+            assert_eq!(
+                dfa_machine.run(input.to_string()),
+                nfa_machine.run(input.to_string()),
+                "{} against {}",
+                pattern,
+                input
+            );
+        }
+    }
+}
+
+#[test]
+fn dfa_is_minimal() {
+    // a, b, "anything else" leads to the dead state
+    let dfa = Compiler::compile_dfa(r"^(a|b)*abb$").unwrap();
+    assert_eq!(dfa.transitions.len(), 5);
+    assert_eq!(dfa.classes.len(), 3);
+
+    // once a match is found the search stops in a single accepting state
+    let dfa = Compiler::compile_dfa(r"abc").unwrap();
+    assert_eq!(dfa.transitions.len(), 4);
+    assert_eq!(dfa.accepting.iter().filter(|is_accepting| **is_accepting).count(), 1);
+}
+
+// a Unicode \b is lowered to the ASCII one: the bytes of non-ASCII
+// characters are non-word bytes
+#[test]
+fn unicode_word_boundary_should_use_ascii_word_bytes() {
+    let program = Compiler::compile(r"\bcaf\b").unwrap();
+    let mut machine = Machine::new(program);
+    assert_eq!(machine.find("un caf\u{e9}".to_string()), Some((3, 6)));
+
+    let program = Compiler::compile(r"caf\B").unwrap();
+    let mut machine = Machine::new(program);
+    assert_eq!(machine.find("caf\u{e9} cafe".to_string()), Some((6, 9)));
+}
+
+#[test]
+fn compile_invalid_pattern_should_fail() {
+    assert!(matches!(
+        Compiler::compile(r"ab(c"),
+        Err(CompileError::Parse(_))
+    ));
+    assert!(matches!(
+        Compiler::compile(r"a{2,1}"),
+        Err(CompileError::Parse(_))
+    ));
+}
+
+#[test]
+fn byte_class_should_succeed() {
+    let program = Compiler::compile(r"ab(?-u:[a-z])").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("abc".to_string()));
+
+    // "\u{e9}" is encoded as the two bytes 0xc3 0xa9
+    let program = Compiler::compile(r"(?-u)^a[\x80-\xff]{2}b$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("a\u{e9}b".to_string()));
+
+    let program = Compiler::compile(r"(?-u)^[\x80-\xff]+$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("\u{e9}\u{e8}".to_string()));
+}
+
+#[test]
+fn byte_class_should_fail() {
+    let program = Compiler::compile(r"(?-u)^a[\x80-\xff]{2}b$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("axyb".to_string()));
+
+    let program = Compiler::compile(r"(?-u)^[\x80-\xff]+$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("\u{e9}e".to_string()));
+}
+
+#[test]
+fn compile_nfa_assertion_should_fail() {
+    for (pattern, expected) in [
+        (r"(?m)^foo$", (Construct::StartLine, 4, 5)),
+        (r"^foo(?m:$)", (Construct::EndLine, 8, 9)),
+        (r"a\bcat", (Construct::WordBoundary, 1, 3)),
+    ] {
+        match Compiler::compile_nfa(pattern) {
+            Err(CompileError::Unsupported { construct, span }) => {
+                assert_eq!((construct, span.start.offset, span.end.offset), expected)
+            }
+            _ => panic!("{} should not be supported", pattern),
+        }
+    }
+}
+
+#[test]
+fn multiline_anchors_should_succeed() {
+    let program = Compiler::compile(r"(?m)^foo$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("foo".to_string()));
+    machine.reset();
+    assert!(machine.run("bar\nfoo\nbaz".to_string()));
+    machine.reset();
+    assert!(machine.run("bar\nfoo".to_string()));
+
+    let program = Compiler::compile(r"(?m)^b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("a\nb".to_string()));
+}
+
+#[test]
+fn multiline_anchors_should_fail() {
+    let program = Compiler::compile(r"(?m)^foo$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("barfoo".to_string()));
+    machine.reset();
+    assert!(!machine.run("bar\nfoox".to_string()));
+    machine.reset();
+    assert!(!machine.run("".to_string()));
+
+    let program = Compiler::compile(r"(?m)^b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("ab".to_string()));
+}
+
+#[test]
+fn non_ascii_literal_should_succeed() {
+    let program = Compiler::compile("^caf\u{e9}$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("caf\u{e9}".to_string()));
+
+    let program = Compiler::compile("\u{1f600}+!").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("ok \u{1f600}\u{1f600}!".to_string()));
+}
+
+#[test]
+fn non_ascii_literal_should_fail() {
+    let program = Compiler::compile("^caf\u{e9}$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("cafe".to_string()));
+    machine.reset();
+    assert!(!machine.run("caf\u{e8}".to_string()));
+}
+
+#[test]
+fn unicode_class_should_succeed() {
+    let program = Compiler::compile(r"^[\x{3b1}-\x{3c9}]+$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("\u{3bb}\u{3bf}\u{3b3}\u{3bf}\u{3c2}".to_string()));
+
+    // one, two, three and four byte encodings in the same class
+    let program = Compiler::compile(r"^a[x\x{e9}\x{20ac}\x{1f600}]{2}b$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("ax\u{1f600}b".to_string()));
+    machine.reset();
+    assert!(machine.run("a\u{20ac}\u{e9}b".to_string()));
+}
+
+#[test]
+fn unicode_class_should_fail() {
+    let program = Compiler::compile(r"^[\x{3b1}-\x{3c9}]+$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("\u{391}".to_string()));
+    machine.reset();
+    assert!(!machine.run("\u{3bb}a".to_string()));
+
+    // the bytes of a character can't be mixed with the ones of another
+    let program = Compiler::compile(r"^[\x{e9}\x{20ac}]$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("\u{e8}".to_string()));
+    machine.reset();
+    assert!(!machine.run("\u{e9}\u{20ac}".to_string()));
+}
+
+#[test]
+fn word_boundary_should_succeed() {
+    let program = Compiler::compile(r"\bcat\b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("cat".to_string()));
+    machine.reset();
+    assert!(machine.run("the cat sat".to_string()));
+    machine.reset();
+    assert!(machine.run("(cat)".to_string()));
+
+    let program = Compiler::compile(r"\Bat\b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("cat".to_string()));
+}
+
+#[test]
+fn word_boundary_should_fail() {
+    let program = Compiler::compile(r"\bcat\b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("concatenate".to_string()));
+    machine.reset();
+    assert!(!machine.run("cats".to_string()));
+    machine.reset();
+    assert!(!machine.run("_cat".to_string()));
+
+    let program = Compiler::compile(r"\Bat\b").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("at".to_string()));
+    machine.reset();
+    assert!(!machine.run("a at".to_string()));
+}
+
+#[test]
+fn group_repetition_should_succeed() {
+    let program = Compiler::compile(r"^(ab)+c$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("abc".to_string()));
+    machine.reset();
+    assert!(machine.run("abababc".to_string()));
+}
+
+#[test]
+fn group_repetition_should_fail() {
+    let program = Compiler::compile(r"^(ab)+c$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("c".to_string()));
+    machine.reset();
+    assert!(!machine.run("abac".to_string()));
+}
+
+#[test]
+fn group_repetition_at_end_should_succeed() {
+    let program = Compiler::compile(r"^x(ab)*$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("x".to_string()));
+    machine.reset();
+    assert!(machine.run("xabab".to_string()));
+    machine.reset();
+    assert!(!machine.run("xaba".to_string()));
+}
+
+#[test]
+fn alternation_repetition_should_succeed() {
+    let program = Compiler::compile(r"^(a|b){2,3}c$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(machine.run("abc".to_string()));
+    machine.reset();
+    assert!(machine.run("bbac".to_string()));
+}
+
+#[test]
+fn alternation_repetition_should_fail() {
+    let program = Compiler::compile(r"^(a|b){2,3}c$").unwrap();
+    let mut machine = Machine::new(program);
+    assert!(!machine.run("ac".to_string()));
+    machine.reset();
+    assert!(!machine.run("ababc".to_string()));
+}
+
+#[test]
+fn group_repetition_variants() {
+    for (pattern, inputs) in [
+        (r"^(ab)?c$", vec![("c", true), ("abc", true), ("ababc", false)]),
+        (r"^(ab){2}$", vec![("abab", true), ("ab", false), ("ababab", false)]),
+        (r"^(ab){2,}$", vec![("abab", true), ("ababab", true), ("ab", false)]),
+        (r"^(a(bc)+)+$", vec![("abcabcbc", true), ("abca", false)]),
+    ] {
+        for (input, expected) in inputs {
+            let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+            assert_eq!(machine.run(input.to_string()), expected, "{} against {}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn repetition_of_empty_match_should_succeed() {
+    for (pattern, inputs) in [
+        (r"^(a*)+b$", vec![("b", true), ("aab", true), ("aa", false)]),
+        (r"^(a|b?)+$", vec![("", true), ("abba", true), ("abc", false)]),
+        (r"^(?:a?)+x$", vec![("x", true), ("aax", true), ("aab", false)]),
+        (r"^(?:(ab)*c?)+$", vec![("abcab", true), ("cc", true), ("aba", false)]),
+    ] {
+        for (input, expected) in inputs {
+            let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+            assert_eq!(machine.run(input.to_string()), expected, "{} against {}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn three_way_alternation_should_succeed() {
+    let program = Compiler::compile(r"^(ab|cd|ef)g$").unwrap();
+    for input in ["abg", "cdg", "efg"] {
+        let mut machine = Machine::new(program.clone());
+        assert!(machine.run(input.to_string()), "{}", input);
+    }
+}
+
+#[test]
+fn three_way_alternation_should_fail() {
+    let program = Compiler::compile(r"^(ab|cd|ef)g$").unwrap();
+    for input in ["adg", "cbg", "eg", "efcdg"] {
+        let mut machine = Machine::new(program.clone());
+        assert!(!machine.run(input.to_string()), "{}", input);
+    }
+}
+
+#[test]
+fn top_level_multi_way_alternation() {
+    let program = Compiler::compile(r"^a$|^b$|^c$|^de$").unwrap();
+    for (input, expected) in [("a", true), ("c", true), ("de", true), ("d", false), ("e", false)] {
+        let mut machine = Machine::new(program.clone());
+        assert_eq!(machine.run(input.to_string()), expected, "{}", input);
+    }
+}
+
+#[test]
+fn nested_alternation_should_succeed() {
+    let program = Compiler::compile(r"^(a|(b|c)d)e$").unwrap();
+    for input in ["ae", "bde", "cde"] {
+        let mut machine = Machine::new(program.clone());
+        assert!(machine.run(input.to_string()), "{}", input);
+    }
+}
+
+#[test]
+fn nested_alternation_should_fail() {
+    let program = Compiler::compile(r"^(a|(b|c)d)e$").unwrap();
+    for input in ["be", "ade", "de", "cdd"] {
+        let mut machine = Machine::new(program.clone());
+        assert!(!machine.run(input.to_string()), "{}", input);
+    }
+}
+
+#[test]
+fn deeply_nested_alternation() {
+    let program = Compiler::compile(r"^x(ab|c(d|ef|g(h|i)))y$").unwrap();
+    for (input, expected) in [
+        ("xaby", true),
+        ("xcdy", true),
+        ("xcefy", true),
+        ("xcghy", true),
+        ("xcgiy", true),
+        ("xcgy", false),
+        ("xcey", false),
+        ("xay", false),
+    ] {
+        let mut machine = Machine::new(program.clone());
+        assert_eq!(machine.run(input.to_string()), expected, "{}", input);
+    }
+}
+
+// Patterns and inputs on which every machine must agree with the regex crate.
+pub(crate) const SEARCH_CASES: &[(&str, &[&str])] = &[
+    (r"ab", &["aab", "ba", "b", "xxab", ""]),
+    (r"abc", &["aabc", "ababc", "abab"]),
+    (r"a*ab", &["aab", "ab", "b", "aaaab"]),
+    (r"a+b", &["aaab", "b", "ab"]),
+    (r"x?xy", &["xy", "xxy", "y"]),
+    (r"[a-c]*c", &["abcc", "ab", "c"]),
+    (r"[a-c]+c$", &["abc", "c", "acc "]),
+    (r"[a-c]?cd", &["bcd", "cd", "ccd", "cxd"]),
+    (r"^ab|cd$", &["abx", "xcd", "xabcdx"]),
+    (r"(a$|b)c", &["ac", "bc", "a"]),
+    (r"a(b|)c", &["ac", "abc", "abbc"]),
+    (r"(a|ab)c", &["abc", "ac", "ab"]),
+    (r"(ab)+c", &["ababc", "abac", "xabc"]),
+    (r"a{2,3}b", &["ab", "aab", "aaaab"]),
+    (r"^a{2}$", &["aa", "aaa"]),
+    (r"^$", &["", "a"]),
+    (r"", &["", "a"]),
+    (r"\bfoo\b", &["a foo", "foobar", "foo"]),
+    (r"(?-u:\b)caf(?-u:\b)", &["caf\u{e9}", "cafe", "caf"]),
+    (r"(?m)^b$", &["a\nb\nc", "ab"]),
+    (r"[^ab]c", &["bc", "xc", "c"]),
+    (r"(?i)ab", &["xAB", "aB", "b"]),
+    (r"caf\x{e9}", &["un caf\u{e9}", "cafe"]),
+    (r"(?-u)[\x80-\xff]x", &["\u{e9}x", "ex"]),
+    (r"(a*)+b", &["b", "aab", "xa"]),
+    (r"(a|b?)+", &["ab", "ba", "c"]),
+    (r"(?:a?)+x", &["aax", "x", "a"]),
+];
+
+#[test]
+fn machines_agree_with_regex_crate() {
+    for (pattern, inputs) in SEARCH_CASES {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        // the automata don't support every assertion
+        let automata = Compiler::compile_nfa(pattern).ok().map(|nfa| {
+            let dfa = Compiler::compile_dfa(pattern).unwrap();
+            (NfaMachine::new(nfa), DfaMachine::new(dfa))
+        });
+        for input in inputs.iter() {
+            let expected = regex.is_match(input.as_bytes());
+            assert_eq!(machine.run(input.to_string()), expected, "{} on {:?}", pattern, input);
+            if let Some((nfa_machine, dfa_machine)) = &automata {
+                assert_eq!(nfa_machine.run(input.to_string()), expected, "{} on {:?}", pattern, input);
+                assert_eq!(dfa_machine.run(input.to_string()), expected, "{} on {:?}", pattern, input);
+            }
+        }
+    }
+}
+
+#[test]
+fn find_should_return_leftmost_first_span() {
+    for (pattern, input, expected) in [
+        (r"abc", "xxabcx", Some((2, 5))),
+        (r"a|ab", "xab", Some((1, 2))),
+        (r"ab|a", "xab", Some((1, 3))),
+        (r"b+", "abbbcbb", Some((1, 4))),
+        (r"b+?", "abbbcbb", Some((1, 2))),
+        // the `find` of regex 1.7 gives (0, 3) here, unlike its `captures`
+        (r"(a|ab)(c|bcd)", "abcd", Some((0, 4))),
+        (r"a*", "baa", Some((0, 0))),
+        (r"\bcat\b", "concat cat", Some((7, 10))),
+        (r"caf\x{e9}", "un caf\u{e9}", Some((3, 8))),
+        (r"x", "abc", None),
+        (r"^b", "ab", None),
+    ] {
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        assert_eq!(machine.find(input.to_string()), expected, "{} on {:?}", pattern, input);
+    }
+}
+
+// Cases where the first match in priority order is not the longest one.
+pub(crate) const PRIORITY_CASES: &[(&str, &[&str])] = &[
+    (r"a|ab", &["ab", "xab"]),
+    (r"a*?", &["aa"]),
+    (r"a+?", &["aa"]),
+    (r"a??b", &["ab", "b"]),
+    (r"[a-z]+?b", &["aab", "abab"]),
+    (r"(ab)*?c", &["ababc"]),
+    (r"(ab)+?", &["abab"]),
+    (r"(ab){1,3}?", &["ababab"]),
+    (r"(ab){1,3}", &["ababab"]),
+    (r"(a+?)(a*)", &["aaa"]),
+];
+
+#[test]
+fn find_agrees_with_regex_crate() {
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        for input in inputs.iter() {
+            let expected = regex.find(input.as_bytes()).map(|found| (found.start(), found.end()));
+            assert_eq!(machine.find(input.to_string()), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn captures_should_return_group_spans() {
+    for (pattern, input, expected) in [
+        (r"^[a-z]+@([a-z.]+)$", "bob@mail.com", Some(vec![Some((0, 12)), Some((4, 12))])),
+        (r"(a)|(b)", "xb", Some(vec![Some((1, 2)), None, Some((1, 2))])),
+        (r"(ab)+", "ababx", Some(vec![Some((0, 4)), Some((2, 4))])),
+        (r"a(?:b)(c)", "abc", Some(vec![Some((0, 3)), Some((2, 3))])),
+        (r"^([a-z]+)@", "bob.mail", None),
+    ] {
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        assert_eq!(machine.captures(input.to_string()), expected, "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn captures_agree_with_regex_crate() {
+    let anchored_cases: &[(&str, &[&str])] = &[
+        (r"^[a-z]+@([a-z.]+)$", &["alice@example.org"]),
+        (r"^(\d+)-(\d+)$", &["2023-42"]),
+        (r"^([a-z]*)(x?)$", &["abcx"]),
+        (r"^(a|bc)+$", &["abca"]),
+    ];
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES).chain(anchored_cases) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        for input in inputs.iter() {
+            let expected: Option<Vec<Option<(usize, usize)>>> = regex.captures(input.as_bytes()).map(|captures| {
+                captures
+                    .iter()
+                    .map(|group| group.map(|group| (group.start(), group.end())))
+                    .collect()
+            });
+            assert_eq!(machine.captures(input.to_string()), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn captures_should_not_go_through_every_path() {
+    // exponentially many ways to match the a's, none of them followed by b
+    let input = "a".repeat(64);
+    let mut machine = Machine::new(Compiler::compile(r"^(a|a)*b").unwrap());
+    assert_eq!(machine.captures(input.clone()), None);
+    let mut machine = Machine::new(Compiler::compile(r"(a|a)*").unwrap());
+    assert_eq!(machine.captures(input), Some(vec![Some((0, 64)), Some((63, 64))]));
+}
+
+#[test]
+fn count_matches_should_count_non_overlapping_matches() {
+    for (pattern, input, expected) in [
+        (r"cat", "cat concat category", 3),
+        (r"[0-9]", "a1b22c333", 6),
+        (r"[0-9]+", "a1b22c333", 3),
+        (r"aa", "aaaaa", 2),
+        (r"a*", "baa", 2),
+        (r"^a", "aaa", 1),
+        (r"x", "abc", 0),
+        (r"x?", "", 1),
+        (r"a*?", "aa", 3),
+    ] {
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        assert_eq!(machine.count_matches(input.to_string()), expected, "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn count_matches_agrees_with_regex_crate() {
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        for input in inputs.iter() {
+            let expected = regex.find_iter(input.as_bytes()).count();
+            assert_eq!(machine.count_matches(input.to_string()), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn replace_all_should_overwrite_matches() {
+    for (pattern, input, expected) in [
+        (r"[0-9]", "card 1234, cvv 567", "card ****, cvv ***"),
+        (r"\b[a-z]+@[a-z.]+\b", "mail bob@mail.com now", "mail ************ now"),
+        (r"aa", "aaaaa", "****a"),
+        (r"x*", "axb", "a*b"),
+        (r"z", "abc", "abc"),
+    ] {
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        assert_eq!(machine.replace_all(input.to_string(), b'*'), expected.as_bytes(), "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn replace_all_agrees_with_regex_crate() {
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let mut machine = Machine::new(Compiler::compile(pattern).unwrap());
+        for input in inputs.iter() {
+            let mut expected = input.as_bytes().to_vec();
+            for found in regex.find_iter(input.as_bytes()) {
+                expected[found.start()..found.end()].fill(b'#');
+            }
+            assert_eq!(machine.replace_all(input.to_string(), b'#'), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn compile_set_should_share_prefixes_and_comparisons() {
+    let set = Compiler::compile_set(&[r"^abc", r"^abd", r"x[a-c]", r"[a-c]y"]).unwrap();
+    let count = |byte: u8| {
+        set.program
+            .iter()
+            .filter(|item| matches!(item.instruction, Instruction::Char(c) if c == byte))
+            .count()
+    };
+    assert_eq!(count(b'a'), 1);
+    assert_eq!(count(b'b'), 1);
+    assert_eq!(count(b'c'), 1);
+    assert_eq!(set.accepts.len(), 4);
+    for (index, accept) in set.accepts.iter().enumerate() {
+        assert!(matches!(set.program[*accept].instruction, Instruction::Accept(i) if i == index));
+    }
+    // a, b, c, d, x, y and [a-c]
+    let comparisons: std::collections::HashSet<usize> = set.comparisons.iter().flatten().copied().collect();
+    assert_eq!(comparisons.len(), 7);
+}
+
+#[test]
+fn compile_set_should_keep_each_pattern() {
+    let patterns: Vec<&str> = SEARCH_CASES.iter().map(|(pattern, _)| *pattern).collect();
+    let set = Compiler::compile_set(&patterns).unwrap();
+    // the backtracking machine tells whether any of the patterns matches
+    let mut machine = Machine::new(set.program);
+    for (pattern, inputs) in SEARCH_CASES {
+        let mut pattern_machine = Machine::new(Compiler::compile(pattern).unwrap());
+        for input in inputs.iter() {
+            if pattern_machine.run(input.to_string()) {
+                assert!(machine.run(input.to_string()), "{} on {:?}", pattern, input);
+            }
+        }
+    }
+}
+
+#[test]
+fn byte_classes_should_merge_bytes_no_test_tells_apart() {
+    let classes = ByteClasses::new(&Compiler::compile(r"[a-z0-9]+@[a-z]+\.com").unwrap());
+    // digits, the other lowercase letters, c, o, m, @, . and everything else
+    assert_eq!(classes.count, 8);
+    assert_eq!(classes.bits(), 3);
+    let class_of = |byte: u8| classes.map[byte as usize];
+    assert_eq!(class_of(b'0'), class_of(b'9'));
+    assert_eq!(class_of(b'a'), class_of(b'z'));
+    assert_ne!(class_of(b'a'), class_of(b'c'));
+    assert_ne!(class_of(b'a'), class_of(b'0'));
+    assert_eq!(class_of(b'#'), class_of(b'A'));
+    assert_eq!(class_of(0), 0);
+
+    let classes = ByteClasses::new(&Compiler::compile(r"(?m)^\bx").unwrap());
+    // x, the other word bytes, the newline and everything else
+    assert_eq!(classes.count, 4);
+}
@@ -1,28 +1,360 @@
-use tfhe::shortint::{ciphertext::Ciphertext, ServerKey};
-use tfhe_regex::EncodedCipherTrait;
+use std::borrow::Borrow;
+use std::marker::PhantomData;
 
-use crate::program::{CipherInstruction, CipherProgram};
+use tfhe::shortint::{ciphertext::Ciphertext, ClientKey, ServerKey};
+use tfhe_regex::{EncodedCipherTrait, PaddedInput, SelectCipherTrait};
+
+use crate::dfa::CipherDfa;
+use crate::nfa::{CipherNfa, CipherState};
+use crate::program::{
+    is_word_byte, CipherCompiledSet, CipherInstruction, CipherProgram, CipherProgramItem,
+    CiphertextRange, ClearProgram, ClearProgramItem, Instruction,
+};
 
 #[derive(Default, Clone, Debug)]
 struct Context {
     program_counter: usize,
     string_counter: usize,
+    entries: Vec<Option<usize>>,
 }
 
 type Stack = Vec<Context>;
 
-pub struct TFHEMachine<T: EncodedCipherTrait + Clone> {
+/// Runs a program against an encrypted input. The characters of the program
+/// are encrypted too, unless the machine is built with `with_clear_program`.
+pub struct TFHEMachine<T: EncodedCipherTrait + Clone, I = CipherProgramItem<T>> {
     program_counter: usize,
     string_counter: usize,
-    program: CipherProgram<T>,
+    program: Vec<I>,
     stack: Stack,
-    server_key: ServerKey,
+    // position at which each instruction was last reached, for the loops
+    entries: Vec<Option<usize>>,
+    server_key: T::ServerKey,
+    input: PhantomData<T>,
 }
 
 pub trait CheckerCipherTrait {
     fn is_true(&self, ct_result: &Ciphertext) -> bool;
 }
 
+/// Encrypted span of a match, as found by `TFHEMachine::find_oblivious`. The
+/// offsets are written in binary, one encrypted bit per ciphertext starting
+/// with the least significant one, and are all zeros when there is no match.
+pub struct CipherSpan {
+    pub is_match: Ciphertext,
+    pub start: Vec<Ciphertext>,
+    pub end: Vec<Ciphertext>,
+}
+
+impl CipherSpan {
+    /// Decrypts the span on the client side, `None` meaning there is no match.
+    pub fn decrypt(&self, client_key: &ClientKey) -> Option<(usize, usize)> {
+        match client_key.decrypt(&self.is_match) {
+            1 => Some((decrypt_bits(client_key, &self.start), decrypt_bits(client_key, &self.end))),
+            _ => None,
+        }
+    }
+}
+
+/// Encrypted number of matches, as counted by `TFHEMachine::count_matches`.
+/// It is written in binary like the offsets of `CipherSpan`, with enough bits
+/// to count a match at every position of the input.
+pub struct CipherCounter {
+    pub bits: Vec<Ciphertext>,
+}
+
+impl CipherCounter {
+    /// Decrypts the number of matches on the client side.
+    pub fn decrypt(&self, client_key: &ClientKey) -> usize {
+        decrypt_bits(client_key, &self.bits)
+    }
+}
+
+fn decrypt_bits(client_key: &ClientKey, bits: &[Ciphertext]) -> usize {
+    bits.iter()
+        .enumerate()
+        .map(|(bit, ct_bit)| (client_key.decrypt(ct_bit) as usize) << bit)
+        .sum()
+}
+
+// Assertions crossed on the way to a state of the oblivious machine.
+const AFTER_NEWLINE: usize = 1;
+const BEFORE_NEWLINE: usize = 2;
+const WORD_BOUNDARY: usize = 4;
+const NOT_WORD_BOUNDARY: usize = 8;
+const AT_END: usize = 16;
+
+// Encrypted results of the assertions at some position of the input, `None`
+// when they trivially hold or when the program doesn't use them.
+#[derive(Default)]
+struct Boundaries {
+    after_newline: Option<Ciphertext>,
+    before_newline: Option<Ciphertext>,
+    word_boundary: Option<Ciphertext>,
+    not_word_boundary: Option<Ciphertext>,
+    // only when the end of the input is encrypted, see `PaddedInput`
+    at_end: Option<Ciphertext>,
+}
+
+fn ct_or(server_key: &ServerKey, left: &Ciphertext, right: &Ciphertext) -> Ciphertext {
+    let sum = server_key.unchecked_add(left, right);
+    server_key.smart_scalar_greater_or_equal(&sum, 1_u8)
+}
+
+fn ct_not(server_key: &ServerKey, value: &Ciphertext) -> Ciphertext {
+    server_key.smart_scalar_equal(value, 0)
+}
+
+// The gates on two match bits are lookup tables on their sum rather than
+// bivariate ones, which need as much carry space as message space: the
+// machine also runs with parameter sets without carries, such as
+// PARAM_MESSAGE_8_CARRY_0. The tables read the sum over the whole message and
+// carry space, unlike `smart_scalar_equal` which reduces it modulo the message
+// space and would take a sum of 2 for 0 with PARAM_MESSAGE_1_CARRY_1.
+fn ct_and(server_key: &ServerKey, left: &Ciphertext, right: &Ciphertext) -> Ciphertext {
+    let sum = server_key.unchecked_add(left, right);
+    server_key.smart_scalar_greater_or_equal(&sum, 2)
+}
+
+fn ct_xor(server_key: &ServerKey, left: &Ciphertext, right: &Ciphertext) -> Ciphertext {
+    let sum = server_key.unchecked_add(left, right);
+    let accumulator = server_key.generate_accumulator(|sum| (sum == 1) as u64);
+    server_key.keyswitch_programmable_bootstrap(&sum, &accumulator)
+}
+
+fn ct_or_into(server_key: &ServerKey, slot: &mut Option<Ciphertext>, value: &Ciphertext) {
+    *slot = match slot.take() {
+        Some(previous) => Some(ct_or(server_key, &previous, value)),
+        None => Some(value.clone()),
+    };
+}
+
+// Whether the value is in one of the ranges, 0 when there is none
+fn ct_in_ranges<T: EncodedCipherTrait + Clone>(
+    server_key: &T::ServerKey,
+    value: &T,
+    ranges: &[CiphertextRange<T>],
+) -> Ciphertext {
+    let mut result: Option<Ciphertext> = None;
+    for range in ranges.iter() {
+        let in_range = value
+            .clone()
+            .in_range(server_key, range.start.clone(), range.end.clone());
+        ct_or_into(server_key.borrow(), &mut result, &in_range);
+    }
+    result.unwrap_or_else(|| server_key.borrow().create_trivial(0))
+}
+
+fn ct_is_word<T: EncodedCipherTrait + Clone>(
+    server_key: &T::ServerKey,
+    value: &T,
+    ranges: &[CiphertextRange<T>],
+) -> Ciphertext {
+    ct_in_ranges(server_key, value, ranges)
+}
+
+// A way out of the states reached without consuming a character: the test of
+// the next character by the instruction at `test`, or the end of the program
+// when there is none, along with the assertions crossed and the slots saved on
+// the way.
+struct Exit {
+    test: Option<usize>,
+    anchors: usize,
+    saves: Vec<usize>,
+}
+
+// What the backtracking machine does on the input, for the oblivious methods.
+// `exits[position][pc]` lists the ways out of `pc` entered at `position`, in
+// priority order, each one with an encrypted flag telling whether it is the
+// first one leading to the end of the program. `reaches[position][pc]` tells
+// whether the end can be reached at all. Only the states a path can be entered
+// at are filled in: the first one and the ones following a character test.
+struct FirstExits {
+    exits: Vec<Vec<Vec<(Exit, Ciphertext)>>>,
+    reaches: Vec<Vec<Option<Ciphertext>>>,
+}
+
+// Paths moved along their first way out by one position, and the slots they
+// saved on the way when asked for.
+struct FirstStep {
+    next: Vec<Option<Ciphertext>>,
+    ends: Option<Ciphertext>,
+    consumes: Option<Ciphertext>,
+    saves: Vec<Option<Ciphertext>>,
+}
+
+// The match `TFHEMachine::find_oblivious` finds, as one-hot encodings of its
+// start and of its end, and for every position whether the match consumes the
+// character there and whether it saves each slot there.
+struct FirstMatch {
+    is_match: Ciphertext,
+    starts: Vec<Option<Ciphertext>>,
+    ends: Vec<Option<Ciphertext>>,
+    consumes: Vec<Option<Ciphertext>>,
+    saves: Vec<Vec<Option<Ciphertext>>>,
+}
+
+/// What the oblivious machine does at an instruction, leaving out the
+/// characters it tests the input against.
+pub enum Transition {
+    // tests a character, going to `next` when it matches; the test may also
+    // be skipped
+    Test { next: usize, can_skip: bool },
+    Start,
+    Match,
+    // goes on if the anchors hold
+    Assertion(usize),
+    Branch(usize),
+    Jump(usize),
+    Save(usize),
+    // stays there, for the patterns of a set
+    Accept,
+}
+
+/// Instruction of a program run by the oblivious methods of `TFHEMachine`,
+/// whether the characters it tests the input against are encrypted or not.
+pub trait ObliviousInstruction<T: EncodedCipherTrait> {
+    fn transition(&self, pc: usize) -> Transition;
+    // Encrypted result of the character test of the instruction.
+    fn test(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext>;
+    // Whether the input character is a newline, for the line anchors.
+    fn is_newline(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext>;
+    // Whether the input character belongs to \w, for the word boundaries.
+    fn is_word(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext>;
+}
+
+impl<T: EncodedCipherTrait + Clone> ObliviousInstruction<T> for CipherProgramItem<T> {
+    fn transition(&self, pc: usize) -> Transition {
+        match &self.instruction {
+            CipherInstruction::CipherChar(_) => Transition::Test {
+                next: pc + 1,
+                can_skip: false,
+            },
+            CipherInstruction::CipherOptionalChar(_) => Transition::Test {
+                next: pc + 1,
+                can_skip: true,
+            },
+            CipherInstruction::CipherRepetition(_) => Transition::Test {
+                next: pc,
+                can_skip: true,
+            },
+            CipherInstruction::CipherIntervalChar(ranges) => Transition::Test {
+                next: if ranges.can_repeat && !ranges.is_optional { pc } else { pc + 1 },
+                can_skip: ranges.can_repeat || ranges.is_optional,
+            },
+            CipherInstruction::Match => Transition::Match,
+            CipherInstruction::Start => Transition::Start,
+            CipherInstruction::CipherStartLine(_) => Transition::Assertion(AFTER_NEWLINE),
+            CipherInstruction::CipherEndLine(_) => Transition::Assertion(BEFORE_NEWLINE),
+            CipherInstruction::CipherWordBoundary(_) => Transition::Assertion(WORD_BOUNDARY),
+            CipherInstruction::CipherNotWordBoundary(_) => Transition::Assertion(NOT_WORD_BOUNDARY),
+            CipherInstruction::Branch(target) => Transition::Branch(*target),
+            CipherInstruction::Jump(target) => Transition::Jump(*target),
+            CipherInstruction::Save(slot) => Transition::Save(*slot),
+            CipherInstruction::Accept(_) => Transition::Accept,
+        }
+    }
+
+    fn test(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            CipherInstruction::CipherChar(ct)
+            | CipherInstruction::CipherOptionalChar(ct)
+            | CipherInstruction::CipherRepetition(ct) => {
+                Some(ct_input.clone().equal(server_key, ct.clone()))
+            }
+            CipherInstruction::CipherIntervalChar(ranges) => {
+                Some(ct_in_ranges(server_key, ct_input, &ranges.range))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_newline(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            CipherInstruction::CipherStartLine(ct) | CipherInstruction::CipherEndLine(ct) => {
+                Some(ct_input.clone().equal(server_key, ct.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_word(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            CipherInstruction::CipherWordBoundary(ranges)
+            | CipherInstruction::CipherNotWordBoundary(ranges) => {
+                Some(ct_is_word(server_key, ct_input, ranges))
+            }
+            _ => None,
+        }
+    }
+}
+
+// The characters of a cleartext program are compared with the encrypted input
+// using scalar operations, and its classes are evaluated with lookup tables.
+impl<T: EncodedCipherTrait + Clone> ObliviousInstruction<T> for ClearProgramItem {
+    fn transition(&self, pc: usize) -> Transition {
+        match &self.instruction {
+            Instruction::Char(_) => Transition::Test {
+                next: pc + 1,
+                can_skip: false,
+            },
+            Instruction::OptionalChar(_) => Transition::Test {
+                next: pc + 1,
+                can_skip: true,
+            },
+            Instruction::Repetition(_) => Transition::Test {
+                next: pc,
+                can_skip: true,
+            },
+            Instruction::IntervalChar(ranges) => Transition::Test {
+                next: if ranges.can_repeat && !ranges.is_optional { pc } else { pc + 1 },
+                can_skip: ranges.can_repeat || ranges.is_optional,
+            },
+            Instruction::Match => Transition::Match,
+            Instruction::Start => Transition::Start,
+            Instruction::StartLine => Transition::Assertion(AFTER_NEWLINE),
+            Instruction::EndLine => Transition::Assertion(BEFORE_NEWLINE),
+            Instruction::WordBoundary => Transition::Assertion(WORD_BOUNDARY),
+            Instruction::NotWordBoundary => Transition::Assertion(NOT_WORD_BOUNDARY),
+            Instruction::Branch(target) => Transition::Branch(*target),
+            Instruction::Jump(target) => Transition::Jump(*target),
+            Instruction::Save(slot) => Transition::Save(*slot),
+            Instruction::Accept(_) => Transition::Accept,
+        }
+    }
+
+    fn test(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            Instruction::Char(c) | Instruction::OptionalChar(c) | Instruction::Repetition(c) => {
+                Some(ct_input.clone().scalar_equal(server_key, *c))
+            }
+            Instruction::IntervalChar(_) => {
+                self.table.as_ref().map(|table| ct_input.clone().in_table(server_key, table))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_newline(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            Instruction::StartLine | Instruction::EndLine => Some(match &self.table {
+                Some(table) => ct_input.clone().in_table(server_key, table),
+                None => ct_input.clone().scalar_equal(server_key, b'\n'),
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_word(&self, server_key: &T::ServerKey, ct_input: &T) -> Option<Ciphertext> {
+        match &self.instruction {
+            Instruction::WordBoundary | Instruction::NotWordBoundary => {
+                self.table.as_ref().map(|table| ct_input.clone().in_table(server_key, table))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl<T> TFHEMachine<T>
 where
     T: EncodedCipherTrait + Clone,
@@ -33,19 +365,98 @@ where
     }
 
     fn ct_in_range(&self, checker: &impl CheckerCipherTrait, value: T, start: T, end: T) -> bool {
-        let greater = value.clone().greater_or_equal(&self.server_key, start);
-        let less = value.less_or_equal(&self.server_key, end);
-        let result = self.server_key.unchecked_mul_lsb(&less, &greater);
+        let result = value.in_range(&self.server_key, start, end);
         checker.is_true(&result)
     }
 
-    pub fn new(program: CipherProgram<T>, server_key: ServerKey) -> Self {
+    fn ct_is_word(&self, value: &T, ranges: &[CiphertextRange<T>]) -> Ciphertext {
+        ct_is_word(&self.server_key, value, ranges)
+    }
+
+    fn is_word_boundary(
+        &self,
+        checker: &impl CheckerCipherTrait,
+        input: &[T],
+        ranges: &[CiphertextRange<T>],
+    ) -> bool {
+        let is_word = |position: Option<usize>| match position.and_then(|position| input.get(position)) {
+            Some(ct_input) => checker.is_true(&self.ct_is_word(ct_input, ranges)),
+            None => false,
+        };
+        is_word(self.string_counter.checked_sub(1)) != is_word(Some(self.string_counter))
+    }
+
+    /// Runs the program against a cleartext input, for when the pattern is
+    /// the private part and the input is known to the server.
+    ///
+    /// The characters of the pattern stay encrypted and are compared with the
+    /// input bytes using scalar operations, cheaper than comparing two
+    /// ciphertexts. As with `run_oblivious`, the sequence of operations only
+    /// depends on the instructions of the program and on the input, so the
+    /// server learns the shape of the program but not the characters it looks
+    /// for. The returned ciphertext encrypts 1 if the input matches.
+    pub fn run_oblivious_on_plaintext(&self, input: &[u8]) -> Ciphertext {
+        let boundaries = self.clear_boundaries(input);
+        self.run_oblivious_with(input.len(), &boundaries, |position, pc| {
+            let next = self.oblivious_target(pc)?;
+            self.clear_test(pc, input[position]).map(|result| (result, next))
+        })
+    }
+
+    // Encrypted result of the character test of the instruction at `pc`
+    // against a cleartext byte.
+    fn clear_test(&self, pc: usize, byte: u8) -> Option<Ciphertext> {
+        match &self.program[pc].instruction {
+            CipherInstruction::CipherChar(ct)
+            | CipherInstruction::CipherOptionalChar(ct)
+            | CipherInstruction::CipherRepetition(ct) => {
+                Some(ct.clone().scalar_equal(&self.server_key, byte))
+            }
+            CipherInstruction::CipherIntervalChar(ranges) => {
+                let mut result: Option<Ciphertext> = None;
+                for range in ranges.range.iter() {
+                    let greater = range.start.clone().scalar_less_or_equal(&self.server_key, byte);
+                    let less = range.end.clone().scalar_greater_or_equal(&self.server_key, byte);
+                    let in_range = ct_and(self.server_key.borrow(), &less, &greater);
+                    self.ct_or_into(&mut result, &in_range);
+                }
+                result
+            }
+            _ => None,
+        }
+    }
+
+    // Same as `boundaries` for a cleartext input. The assertions only depend on
+    // the input, so they are evaluated in the clear.
+    fn clear_boundaries(&self, input: &[u8]) -> Vec<Boundaries> {
+        let ct_holds = |holds: bool| (!holds).then(|| self.server_key.borrow().create_trivial(0));
+        let is_word = |position: Option<usize>| {
+            matches!(position.and_then(|position| input.get(position)), Some(byte) if is_word_byte(*byte))
+        };
+        (0..=input.len())
+            .map(|position| {
+                let prev = position.checked_sub(1);
+                let is_word_boundary = is_word(prev) != is_word(Some(position));
+                Boundaries {
+                    after_newline: ct_holds(prev.map(|prev| input[prev]).unwrap_or(b'\n') == b'\n'),
+                    before_newline: ct_holds(input.get(position).copied().unwrap_or(b'\n') == b'\n'),
+                    word_boundary: ct_holds(is_word_boundary),
+                    not_word_boundary: ct_holds(!is_word_boundary),
+                    at_end: None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn new(program: CipherProgram<T>, server_key: T::ServerKey) -> Self {
         Self {
             program_counter: 0,
             string_counter: 0,
+            entries: vec![None; program.len()],
             program,
             stack: Stack::new(),
             server_key: server_key,
+            input: PhantomData,
         }
     }
 
@@ -53,152 +464,1116 @@ where
         self.program_counter = 0;
         self.string_counter = 0;
         self.stack = Stack::new();
+        self.entries = vec![None; self.program.len()];
+    }
+
+    // Resumes from the last alternative left aside. Returns false when there is
+    // none, meaning the match attempt has failed.
+    fn backtrack(&mut self) -> bool {
+        match self.stack.pop() {
+            Some(context) => {
+                self.program_counter = context.program_counter;
+                self.string_counter = context.string_counter;
+                self.entries = context.entries;
+                true
+            }
+            None => false,
+        }
     }
 
+    // Leaves the current position aside to come back to `program_counter` there
+    // if what follows fails.
+    fn save(&mut self, program_counter: usize) {
+        self.stack.push(Context {
+            program_counter,
+            string_counter: self.string_counter,
+            entries: self.entries.clone(),
+        });
+    }
+
+    fn input_equals(&self, checker: &impl CheckerCipherTrait, input: &[T], ct: T) -> bool {
+        match input.get(self.string_counter) {
+            Some(ct_input) => self.ct_are_equal(checker, ct_input.clone(), ct),
+            None => false,
+        }
+    }
+
+    /// Searches for the pattern anywhere in the input, as if it was preceded by
+    /// `.*?`: a match is attempted from every position in turn, like
+    /// `machine::Machine::run` does.
     pub fn run(&mut self, input: Vec<T>, checker: &impl CheckerCipherTrait) -> bool {
-        let mut state = 0;
-        let mut exact_match = false;
+        (0..=input.len()).any(|start| {
+            self.reset();
+            self.string_counter = start;
+            self.run_from(&input, checker)
+        })
+    }
 
+    // Tries to match the program from the current position of the input.
+    fn run_from(&mut self, input: &[T], checker: &impl CheckerCipherTrait) -> bool {
         while self.program_counter < self.program.len() {
-            if state == self.program.len() {
-                // End of program, return true
-                return true;
-            }
-
-            let current_item = self.program.get(self.program_counter).unwrap();
+            let current_item = self.program[self.program_counter].clone();
+            self.entries[self.program_counter] = Some(self.string_counter);
+            let next_string_counter =
+                (self.string_counter as i32 + current_item.action.offset) as usize;
 
-            match current_item.instruction.clone() {
+            let has_matched = match current_item.instruction {
                 CipherInstruction::CipherChar(ct) => {
-                    if self.string_counter >= input.len() {
-                        return false;
-                    }
-                    let ct_input = input[self.string_counter].clone();
-                    let result = self.ct_are_equal(checker, ct_input, ct);
-                    if !result {
-                        if self.stack.is_empty() {
-                            // Failed match, backtrack to previous state
-                            let prev_state = self.program_counter.saturating_sub(1);
-                            let prev_item = self.program[prev_state].clone();
-                            match prev_item.instruction {
-                                CipherInstruction::Jump(_) => {
-                                    return false;
-                                }
-                                _ => {
-                                    state = prev_item.action.next;
-                                    self.string_counter = (self.string_counter as i32
-                                        + prev_item.action.offset)
-                                        as usize;
-                                    self.program_counter = prev_state;
-                                    if exact_match {
-                                        return false;
-                                    }
-                                }
-                            }
-                        } else {
-                            let context = self.stack.pop().unwrap();
-                            self.program_counter = context.program_counter;
-                            self.string_counter = context.string_counter;
-                        }
-                    } else {
-                        // Successful match, advance to next state
-                        state = current_item.action.next;
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                        self.program_counter += 1;
+                    let result = self.input_equals(checker, input, ct);
+                    if result {
+                        self.string_counter = next_string_counter;
                     }
+                    result
+                }
+                CipherInstruction::Match => self.string_counter == input.len(),
+                CipherInstruction::Start => self.string_counter == 0,
+                CipherInstruction::CipherStartLine(ct) => {
+                    self.string_counter == 0
+                        || self.ct_are_equal(checker, input[self.string_counter - 1].clone(), ct)
                 }
-                CipherInstruction::Match => {
-                    // check qu'on en est à la fin de la string
-                    return self.string_counter == input.len();
+                CipherInstruction::CipherEndLine(ct) => {
+                    self.string_counter == input.len() || self.input_equals(checker, input, ct)
                 }
-                CipherInstruction::Start => {
-                    self.program_counter += 1;
-                    exact_match = true;
+                CipherInstruction::CipherWordBoundary(ranges) => {
+                    self.is_word_boundary(checker, input, &ranges)
+                }
+                CipherInstruction::CipherNotWordBoundary(ranges) => {
+                    !self.is_word_boundary(checker, input, &ranges)
                 }
                 CipherInstruction::CipherRepetition(ct) => {
-                    let ct_input = input[self.string_counter].clone();
-                    let result = self.ct_are_equal(checker, ct_input, ct);
-                    if result {
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                    } else {
-                        state = current_item.action.next;
-                        self.program_counter += 1;
+                    if self.input_equals(checker, input, ct) {
+                        // greedy: try once more first, stop repeating otherwise
+                        self.save(self.program_counter + 1);
+                        self.string_counter = next_string_counter;
+                        continue;
                     }
+                    true
                 }
                 CipherInstruction::CipherOptionalChar(ct) => {
-                    let ct_input = input[self.string_counter].clone();
-                    let result = self.ct_are_equal(checker, ct_input, ct);
-                    if result {
-                        // if it matches we will go next character of the string
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
+                    if self.input_equals(checker, input, ct) {
+                        self.save(self.program_counter + 1);
+                        self.string_counter = next_string_counter;
                     }
-                    // if it doesn't match then we stay at the same sc but fo on pc right to the next state and next instruction
-                    state = current_item.action.next;
-                    self.program_counter += 1;
+                    true
                 }
                 CipherInstruction::CipherIntervalChar(ranges) => {
-                    let mut has_matched = false;
-                    let ct_input = input[self.string_counter].clone();
-                    for range in ranges.range.iter() {
-                        if self.ct_in_range(
-                            checker,
-                            ct_input.clone(),
-                            range.start.clone(),
-                            range.end.clone(),
-                        ) {
-                            // we're in the right range, it matches
-                            has_matched = true;
-                            break;
+                    let in_range = match input.get(self.string_counter) {
+                        Some(ct_input) => ranges.range.iter().any(|range| {
+                            self.ct_in_range(
+                                checker,
+                                ct_input.clone(),
+                                range.start.clone(),
+                                range.end.clone(),
+                            )
+                        }),
+                        None => false,
+                    };
+                    if !in_range {
+                        ranges.is_optional || ranges.can_repeat
+                    } else {
+                        if ranges.is_optional || ranges.can_repeat {
+                            self.save(self.program_counter + 1);
                         }
-                    }
-                    if has_matched {
-                        self.string_counter =
-                            (self.string_counter as i32 + current_item.action.offset) as usize;
-                        if !ranges.can_repeat || ranges.is_optional {
-                            state = current_item.action.next;
-                            self.program_counter += 1;
+                        self.string_counter = next_string_counter;
+                        if ranges.can_repeat && !ranges.is_optional {
+                            continue;
                         }
-                    } else if !has_matched && (ranges.is_optional || ranges.can_repeat) {
-                        state = current_item.action.next;
-                        self.program_counter += 1;
-                    } else if self.stack.is_empty() {
-                        let prev_state = self.program_counter.saturating_sub(1);
-                        let prev_item = self.program[prev_state].clone();
-                        match prev_item.instruction {
-                            CipherInstruction::Jump(_) => {
-                                return false;
-                            }
-                            _ => {
-                                state = prev_item.action.next;
-                                self.string_counter =
-                                    (self.string_counter as i32 + prev_item.action.offset) as usize;
-                                self.program_counter = prev_state;
-                                if exact_match {
-                                    return false;
-                                }
-                            }
-                        }
-                    } else {
-                        let context = self.stack.pop().unwrap();
-                        self.program_counter = context.program_counter;
-                        self.string_counter = context.string_counter;
+                        true
                     }
                 }
                 CipherInstruction::Branch(pc) => {
-                    let context = Context {
-                        program_counter: pc,
-                        string_counter: self.string_counter,
-                    };
-                    self.stack.push(context);
-                    self.program_counter += 1;
+                    self.save(pc);
+                    true
+                }
+                // an iteration of a loop that consumed nothing fails, see
+                // `machine::Machine`
+                CipherInstruction::Jump(pc)
+                    if pc < self.program_counter && self.entries[pc] == Some(self.string_counter) =>
+                {
+                    false
                 }
                 CipherInstruction::Jump(pc) => {
                     self.program_counter = pc;
+                    continue;
                 }
+                CipherInstruction::Save(_) => true,
+                CipherInstruction::Accept(_) => return true,
+            };
+
+            if has_matched {
+                self.program_counter += 1;
+            } else if !self.backtrack() {
+                return false;
             }
         }
         true
     }
 }
+
+impl<T> TFHEMachine<T, ClearProgramItem>
+where
+    T: EncodedCipherTrait + Clone,
+{
+    /// Builds a machine for a program whose characters are not secret, as
+    /// returned by `program::clear_program`. They are compared with the
+    /// encrypted input using scalar operations and lookup tables, much cheaper
+    /// than the comparisons between two ciphertexts needed when the program is
+    /// encrypted. Only the oblivious methods are available.
+    pub fn with_clear_program(program: ClearProgram, server_key: T::ServerKey) -> Self {
+        Self {
+            program_counter: 0,
+            string_counter: 0,
+            entries: vec![],
+            program,
+            stack: Stack::new(),
+            server_key,
+            input: PhantomData,
+        }
+    }
+}
+
+impl<T, I> TFHEMachine<T, I>
+where
+    T: EncodedCipherTrait + Clone,
+    I: ObliviousInstruction<T>,
+{
+    fn ct_or_into(&self, slot: &mut Option<Ciphertext>, value: &Ciphertext) {
+        ct_or_into(self.server_key.borrow(), slot, value)
+    }
+
+    // States reachable from `pc` without consuming a character, along with the
+    // assertions crossed on the way. `Start` and `Match` only let the machine
+    // through at the very beginning and at the very end of the input, `Match`
+    // also asserting `AT_END` for when the end of the input is encrypted.
+    fn epsilon_closure(&self, pc: usize, at_start: bool, at_end: bool) -> Vec<(usize, usize)> {
+        let mut closure = vec![];
+        let mut visited = vec![[false; 32]; self.program.len() + 1];
+        let mut todo = vec![(pc, 0)];
+        while let Some((pc, anchors)) = todo.pop() {
+            if visited[pc][anchors] {
+                continue;
+            }
+            visited[pc][anchors] = true;
+            closure.push((pc, anchors));
+            if pc == self.program.len() {
+                continue;
+            }
+            match self.program[pc].transition(pc) {
+                Transition::Start => {
+                    if at_start {
+                        todo.push((pc + 1, anchors));
+                    }
+                }
+                Transition::Match => {
+                    if at_end {
+                        todo.push((pc + 1, anchors | AT_END));
+                    }
+                }
+                Transition::Assertion(anchor) => todo.push((pc + 1, anchors | anchor)),
+                Transition::Test { can_skip, .. } => {
+                    if can_skip {
+                        todo.push((pc + 1, anchors));
+                    }
+                }
+                Transition::Branch(target) => {
+                    todo.push((target, anchors));
+                    todo.push((pc + 1, anchors));
+                }
+                Transition::Jump(target) => todo.push((target, anchors)),
+                Transition::Save(_) => todo.push((pc + 1, anchors)),
+                Transition::Accept => {}
+            }
+        }
+        // a state reached through fewer anchors makes the other paths useless
+        closure
+            .iter()
+            .filter(|(pc, anchors)| {
+                !closure.iter().any(|(other_pc, other_anchors)| {
+                    other_pc == pc && other_anchors != anchors && other_anchors & anchors == *other_anchors
+                })
+            })
+            .copied()
+            .collect()
+    }
+
+    // Encrypted results of the assertions of the program at every position of
+    // the input, from before the first character to after the last one.
+    fn boundaries(&self, input: &[T]) -> Vec<Boundaries> {
+        let mut boundaries: Vec<Boundaries> = (0..=input.len()).map(|_| Boundaries::default()).collect();
+
+        let line_anchor = self.program.iter().enumerate().find_map(|(pc, item)| {
+            match item.transition(pc) {
+                Transition::Assertion(AFTER_NEWLINE | BEFORE_NEWLINE) => Some(item),
+                _ => None,
+            }
+        });
+        if let Some(line_anchor) = line_anchor {
+            for (position, ct_input) in input.iter().enumerate() {
+                let ct_is_newline = line_anchor.is_newline(&self.server_key, ct_input);
+                boundaries[position].before_newline = ct_is_newline.clone();
+                boundaries[position + 1].after_newline = ct_is_newline;
+            }
+        }
+
+        let word_boundary = self.program.iter().enumerate().find_map(|(pc, item)| {
+            match item.transition(pc) {
+                Transition::Assertion(WORD_BOUNDARY | NOT_WORD_BOUNDARY) => Some(item),
+                _ => None,
+            }
+        });
+        if let Some(word_boundary) = word_boundary {
+            // there are no word characters outside of the input
+            let ct_false = self.server_key.borrow().create_trivial(0);
+            let ct_words: Vec<Ciphertext> = input
+                .iter()
+                .map(|ct_input| {
+                    let ct_is_word = word_boundary.is_word(&self.server_key, ct_input);
+                    ct_is_word.unwrap_or_else(|| ct_false.clone())
+                })
+                .collect();
+            for (position, boundary) in boundaries.iter_mut().enumerate() {
+                let ct_prev = position.checked_sub(1).map_or(&ct_false, |prev| &ct_words[prev]);
+                let ct_current = ct_words.get(position).unwrap_or(&ct_false);
+                let ct_word_boundary = ct_xor(self.server_key.borrow(), ct_prev, ct_current);
+                boundary.not_word_boundary = Some(ct_not(self.server_key.borrow(), &ct_word_boundary));
+                boundary.word_boundary = Some(ct_word_boundary);
+            }
+        }
+        boundaries
+    }
+
+    // Encrypted result of the character test performed by the instruction at
+    // `pc`, along with the state reached when it succeeds.
+    fn oblivious_step(&self, pc: usize, ct_input: &T) -> Option<(Ciphertext, usize)> {
+        let next = self.oblivious_target(pc)?;
+        self.oblivious_test(pc, ct_input).map(|result| (result, next))
+    }
+
+    // State reached when the character test of the instruction at `pc`
+    // succeeds, if it performs one.
+    fn oblivious_target(&self, pc: usize) -> Option<usize> {
+        match self.program[pc].transition(pc) {
+            Transition::Test { next, .. } => Some(next),
+            _ => None,
+        }
+    }
+
+    fn oblivious_test(&self, pc: usize, ct_input: &T) -> Option<Ciphertext> {
+        self.program[pc].test(&self.server_key, ct_input)
+    }
+
+    fn activate(
+        &self,
+        states: &mut [Option<Ciphertext>],
+        pc: usize,
+        value: &Ciphertext,
+        at_start: bool,
+        at_end: bool,
+        boundaries: &Boundaries,
+    ) {
+        for (state, anchors) in self.epsilon_closure(pc, at_start, at_end) {
+            let ct_value = self.ct_gate(value, anchors, boundaries);
+            self.ct_or_into(&mut states[state], &ct_value);
+        }
+    }
+
+    // `value` restricted to the positions where the given anchors hold.
+    fn ct_gate(&self, value: &Ciphertext, anchors: usize, boundaries: &Boundaries) -> Ciphertext {
+        let mut ct_value = value.clone();
+        for (anchor, boundary) in [
+            (AFTER_NEWLINE, &boundaries.after_newline),
+            (BEFORE_NEWLINE, &boundaries.before_newline),
+            (WORD_BOUNDARY, &boundaries.word_boundary),
+            (NOT_WORD_BOUNDARY, &boundaries.not_word_boundary),
+            (AT_END, &boundaries.at_end),
+        ] {
+            if let (true, Some(ct_boundary)) = (anchors & anchor != 0, boundary) {
+                ct_value = ct_and(self.server_key.borrow(), &ct_value, ct_boundary);
+            }
+        }
+        ct_value
+    }
+
+    /// Runs the program without decrypting anything on the way.
+    ///
+    /// Every instruction of the program is a state of an automaton, and the set
+    /// of active states is kept as encrypted booleans. Each input character is
+    /// compared against every active state, so the sequence of operations only
+    /// depends on the program and on the length of the input. The returned
+    /// ciphertext encrypts 1 if the input matches and 0 otherwise; it is meant to
+    /// be decrypted by the client.
+    pub fn run_oblivious(&self, input: Vec<T>) -> Ciphertext {
+        let boundaries = self.boundaries(&input);
+        self.run_oblivious_with(input.len(), &boundaries, |position, pc| {
+            self.oblivious_step(pc, &input[position])
+        })
+    }
+
+    /// Same as `run_oblivious` for an input padded to a fixed length, so that
+    /// the server only learns that length and not the one of the input.
+    ///
+    /// Padding characters never match, the automaton only starts on real
+    /// characters or right after the last one, and `$` and the line anchors
+    /// find the end of the input through the encrypted validity mask.
+    pub fn run_oblivious_padded(&self, input: &PaddedInput<T>) -> Ciphertext {
+        let len = input.chars.len();
+        let end = self.program.len();
+        let ct_true = self.server_key.borrow().create_trivial(1);
+        let mut states: Vec<Option<Ciphertext>> = vec![None; end + 1];
+        let mut result: Option<Ciphertext> = None;
+
+        let mut boundaries = self.boundaries(&input.chars);
+        for (position, boundary) in boundaries.iter_mut().enumerate() {
+            // the input ends where the last valid character is followed by padding
+            let before = position.checked_sub(1).map(|prev| &input.valid[prev]);
+            let ct_end = match (before, input.valid.get(position)) {
+                (Some(ct_before), Some(ct_after)) => {
+                    ct_and(self.server_key.borrow(), ct_before, &ct_not(self.server_key.borrow(), ct_after))
+                }
+                (Some(ct_before), None) => ct_before.clone(),
+                (None, Some(ct_after)) => ct_not(self.server_key.borrow(), ct_after),
+                (None, None) => ct_true.clone(),
+            };
+            if let Some(ct_newline) = &boundary.before_newline {
+                boundary.before_newline = Some(ct_or(self.server_key.borrow(), ct_newline, &ct_end));
+            }
+            boundary.at_end = Some(ct_end);
+        }
+
+        for position in 0..=len {
+            // the pattern may start matching anywhere up to the end of the input
+            let ct_start = match position.checked_sub(1) {
+                Some(prev) => input.valid[prev].clone(),
+                None => ct_true.clone(),
+            };
+            self.activate(&mut states, 0, &ct_start, position == 0, true, &boundaries[position]);
+            if let Some(ct_end) = states[end].take() {
+                self.ct_or_into(&mut result, &ct_end);
+            }
+            if position == len {
+                break;
+            }
+            states = self.oblivious_advance_with(&states, true, &boundaries[position + 1], |pc| {
+                let (ct_result, next) = self.oblivious_step(pc, &input.chars[position])?;
+                let ct_valid = ct_and(self.server_key.borrow(), &ct_result, &input.valid[position]);
+                Some((ct_valid, next))
+            });
+        }
+        result.unwrap_or_else(|| self.server_key.borrow().create_trivial(0))
+    }
+
+    // Runs the automaton of `run_oblivious` over an input of the given length,
+    // the character tests being given by `step`.
+    fn run_oblivious_with(
+        &self,
+        len: usize,
+        boundaries: &[Boundaries],
+        step: impl Fn(usize, usize) -> Option<(Ciphertext, usize)>,
+    ) -> Ciphertext {
+        let end = self.program.len();
+        let ct_true = self.server_key.borrow().create_trivial(1);
+        let mut states: Vec<Option<Ciphertext>> = vec![None; end + 1];
+        let mut result: Option<Ciphertext> = None;
+
+        for position in 0..len {
+            // the pattern may start matching at any position
+            self.activate(&mut states, 0, &ct_true, position == 0, false, &boundaries[position]);
+            if let Some(ct_end) = states[end].take() {
+                self.ct_or_into(&mut result, &ct_end);
+            }
+            states = self.oblivious_advance_with(&states, position + 1 == len, &boundaries[position + 1], |pc| {
+                step(position, pc)
+            });
+        }
+
+        self.activate(&mut states, 0, &ct_true, len == 0, true, &boundaries[len]);
+        if let Some(ct_end) = &states[end] {
+            self.ct_or_into(&mut result, ct_end);
+        }
+        result.unwrap_or_else(|| self.server_key.borrow().create_trivial(0))
+    }
+
+    // States active after a character, given the ones active before it and
+    // the character tests given by `step`.
+    fn oblivious_advance_with(
+        &self,
+        states: &[Option<Ciphertext>],
+        at_end: bool,
+        boundaries: &Boundaries,
+        step: impl Fn(usize) -> Option<(Ciphertext, usize)>,
+    ) -> Vec<Option<Ciphertext>> {
+        let end = self.program.len();
+        let mut next_states: Vec<Option<Ciphertext>> = vec![None; end + 1];
+        for (pc, state) in states.iter().enumerate().take(end) {
+            let ct_active = match state {
+                Some(ct_active) => ct_active,
+                None => continue,
+            };
+            if let Some((ct_result, next)) = step(pc) {
+                let ct_next = ct_and(self.server_key.borrow(), ct_active, &ct_result);
+                self.activate(&mut next_states, next, &ct_next, false, at_end, boundaries);
+            }
+        }
+        next_states
+    }
+
+    // Ways out of the states reached from `pc` without consuming a character,
+    // in the order `machine::Machine` tries them: the instructions after a
+    // branch before its target, and a character test before the instruction
+    // after it when it may be skipped. Like the visited positions of the
+    // machine, a state reached again through the same assertions or more of
+    // them is not gone through twice.
+    fn priority_closure(&self, pc: usize, at_start: bool, at_end: bool) -> Vec<Exit> {
+        let mut exits = vec![];
+        let mut visited: Vec<Vec<usize>> = vec![vec![]; self.program.len() + 1];
+        let mut todo = vec![(pc, 0, vec![])];
+        while let Some((pc, anchors, saves)) = todo.pop() {
+            if visited[pc].iter().any(|other| other & anchors == *other) {
+                continue;
+            }
+            visited[pc].push(anchors);
+            if pc == self.program.len() {
+                exits.push(Exit { test: None, anchors, saves });
+                continue;
+            }
+            // pushed in reverse order, the first one to try coming out first
+            match self.program[pc].transition(pc) {
+                Transition::Start => {
+                    if at_start {
+                        todo.push((pc + 1, anchors, saves));
+                    }
+                }
+                Transition::Match => {
+                    if at_end {
+                        todo.push((pc + 1, anchors | AT_END, saves));
+                    }
+                }
+                Transition::Assertion(anchor) => todo.push((pc + 1, anchors | anchor, saves)),
+                Transition::Test { can_skip, .. } => {
+                    if can_skip {
+                        todo.push((pc + 1, anchors, saves.clone()));
+                    }
+                    exits.push(Exit { test: Some(pc), anchors, saves });
+                }
+                Transition::Branch(target) => {
+                    todo.push((target, anchors, saves.clone()));
+                    todo.push((pc + 1, anchors, saves));
+                }
+                Transition::Jump(target) => todo.push((target, anchors, saves)),
+                Transition::Save(slot) => {
+                    let mut saves = saves;
+                    saves.push(slot);
+                    todo.push((pc + 1, anchors, saves));
+                }
+                Transition::Accept => {}
+            }
+        }
+        exits
+    }
+
+    // Backwards pass of the leftmost-first search, see `FirstExits`.
+    fn first_exits(&self, input: &[T], boundaries: &[Boundaries]) -> FirstExits {
+        let end = self.program.len();
+        let ct_true = self.server_key.borrow().create_trivial(1);
+        // a path enters a state at the start of the program or after a test
+        let mut is_entry = vec![false; end + 1];
+        is_entry[0] = true;
+        for pc in 0..end {
+            if let Some(next) = self.oblivious_target(pc) {
+                is_entry[next] = true;
+            }
+        }
+
+        let mut exits: Vec<Vec<Vec<(Exit, Ciphertext)>>> = (0..=input.len()).map(|_| vec![]).collect();
+        let mut reaches: Vec<Vec<Option<Ciphertext>>> = vec![vec![]; input.len() + 1];
+        for position in (0..=input.len()).rev() {
+            let at_end = position == input.len();
+            let tests: Vec<Option<Ciphertext>> = (0..end)
+                .map(|pc| match at_end {
+                    true => None,
+                    false => self.oblivious_test(pc, &input[position]),
+                })
+                .collect();
+            exits[position] = (0..=end).map(|_| vec![]).collect();
+            reaches[position] = vec![None; end + 1];
+            for pc in (0..=end).filter(|pc| is_entry[*pc]) {
+                // whether an earlier way out leads to the end of the program
+                let mut earlier: Option<Ciphertext> = None;
+                for exit in self.priority_closure(pc, position == 0, at_end) {
+                    let ct_ok = match exit.test {
+                        None => ct_true.clone(),
+                        Some(test) => {
+                            let next = self.oblivious_target(test).unwrap();
+                            let reaches = reaches.get(position + 1).and_then(|reaches| reaches[next].as_ref());
+                            match (&tests[test], reaches) {
+                                (Some(ct_test), Some(ct_reaches)) => {
+                                    ct_and(self.server_key.borrow(), ct_test, ct_reaches)
+                                }
+                                _ => continue,
+                            }
+                        }
+                    };
+                    let ct_ok = self.ct_gate(&ct_ok, exit.anchors, &boundaries[position]);
+                    let ct_first = match &earlier {
+                        Some(ct_earlier) => {
+                            ct_and(self.server_key.borrow(), &ct_ok, &ct_not(self.server_key.borrow(), ct_earlier))
+                        }
+                        None => ct_ok.clone(),
+                    };
+                    self.ct_or_into(&mut earlier, &ct_ok);
+                    // nothing comes after a way out that cannot fail
+                    let cannot_fail = exit.test.is_none() && exit.anchors == 0;
+                    exits[position][pc].push((exit, ct_first));
+                    if cannot_fail {
+                        break;
+                    }
+                }
+                reaches[position][pc] = earlier;
+            }
+        }
+        FirstExits { exits, reaches }
+    }
+
+    // Moves the paths entering the given states at `position` along their
+    // first way out, see `FirstExits`. Returns the states they enter at the
+    // next position, whether one of them ends at `position`, whether one of
+    // them consumes the character there and, given the number of slots to
+    // look at, whether one of them saves each slot.
+    fn follow_first_exits(
+        &self,
+        table: &FirstExits,
+        position: usize,
+        entries: &[Option<Ciphertext>],
+        slots: usize,
+    ) -> FirstStep {
+        let mut step = FirstStep {
+            next: vec![None; self.program.len() + 1],
+            ends: None,
+            consumes: None,
+            saves: vec![None; slots],
+        };
+        for (pc, entry) in entries.iter().enumerate() {
+            let ct_entry = match entry {
+                Some(ct_entry) => ct_entry,
+                None => continue,
+            };
+            for (exit, ct_first) in table.exits[position][pc].iter() {
+                let ct_taken = ct_and(self.server_key.borrow(), ct_entry, ct_first);
+                match exit.test {
+                    None => self.ct_or_into(&mut step.ends, &ct_taken),
+                    Some(test) => {
+                        let next = self.oblivious_target(test).unwrap();
+                        self.ct_or_into(&mut step.next[next], &ct_taken);
+                        self.ct_or_into(&mut step.consumes, &ct_taken);
+                    }
+                }
+                for slot in exit.saves.iter().filter(|slot| **slot < slots) {
+                    self.ct_or_into(&mut step.saves[*slot], &ct_taken);
+                }
+            }
+        }
+        step
+    }
+
+    // Follows the path of the leftmost-first match, see `find_oblivious`,
+    // looking at the given number of slots on the way.
+    fn first_match(&self, input: &[T], slots: usize) -> FirstMatch {
+        let boundaries = self.boundaries(input);
+        let table = self.first_exits(input, &boundaries);
+        // whether a match starts before the current start position
+        let mut found: Option<Ciphertext> = None;
+        let mut first_match = FirstMatch {
+            is_match: self.server_key.borrow().create_trivial(0),
+            starts: vec![],
+            ends: vec![],
+            consumes: vec![],
+            saves: vec![],
+        };
+        let mut entries: Vec<Option<Ciphertext>> = vec![None; self.program.len() + 1];
+
+        for position in 0..=input.len() {
+            let is_leftmost = match (&table.reaches[position][0], &found) {
+                (Some(ct_starts), Some(ct_found)) => {
+                    Some(ct_and(self.server_key.borrow(), ct_starts, &ct_not(self.server_key.borrow(), ct_found)))
+                }
+                (Some(ct_starts), None) => Some(ct_starts.clone()),
+                (None, _) => None,
+            };
+            if let Some(ct_leftmost) = &is_leftmost {
+                self.ct_or_into(&mut entries[0], ct_leftmost);
+            }
+            let step = self.follow_first_exits(&table, position, &entries, slots);
+            entries = step.next;
+            first_match.ends.push(step.ends);
+            first_match.consumes.push(step.consumes);
+            first_match.saves.push(step.saves);
+            if let Some(ct_starts) = &table.reaches[position][0] {
+                self.ct_or_into(&mut found, ct_starts);
+            }
+            first_match.starts.push(is_leftmost);
+        }
+        if let Some(ct_found) = found {
+            first_match.is_match = ct_found;
+        }
+        first_match
+    }
+
+    /// Finds the leftmost match without decrypting anything on the way, and the
+    /// first one in priority order among the matches starting there, like
+    /// `machine::Machine::find`.
+    ///
+    /// Whether the end of the program can be reached from every state at every
+    /// position is computed backwards from the end of the input, which tells
+    /// which way out of a state the backtracking machine would take. The path
+    /// of the match is then followed forwards from its start, as an encrypted
+    /// one-hot vector of states. Both passes cost a few bootstraps per state of
+    /// the program and per position, so the search is linear in the length of
+    /// the input. Only the client can decrypt the returned span.
+    pub fn find_oblivious(&self, input: Vec<T>) -> CipherSpan {
+        let first_match = self.first_match(&input, 0);
+        CipherSpan {
+            is_match: first_match.is_match,
+            start: self.ct_offset(&first_match.starts),
+            end: self.ct_offset(&first_match.ends),
+        }
+    }
+
+    // The non-overlapping matches, as `machine::Machine::count_matches` finds
+    // them. For every position, whether one of them starts there and whether
+    // one of them consumes the character there.
+    //
+    // An encrypted cursor goes through the input, telling whether the search
+    // for the next match stands at the current position. Wherever a match
+    // starts there, its path is followed like in `find_oblivious` and the
+    // cursor stays off until the path ends, or moves to the next position
+    // after an empty match. An empty match right where the previous match
+    // ends is skipped.
+    fn non_overlapping_matches(&self, input: &[T]) -> (Vec<Option<Ciphertext>>, Vec<Option<Ciphertext>>) {
+        let boundaries = self.boundaries(input);
+        let table = self.first_exits(input, &boundaries);
+        let mut founds = vec![];
+        let mut consumed = vec![];
+        let mut cursor: Option<Ciphertext> = Some(self.server_key.borrow().create_trivial(1));
+        let mut entries: Vec<Option<Ciphertext>> = vec![None; self.program.len() + 1];
+
+        for position in 0..=input.len() {
+            // the match under way, which consumed at least a character
+            let step = self.follow_first_exits(&table, position, &entries, 0);
+            if let Some(ct_ends) = &step.ends {
+                self.ct_or_into(&mut cursor, ct_ends);
+            }
+            let mut consumes = step.consumes;
+            entries = step.next;
+
+            let mut starts = table.reaches[position][0].clone();
+            if let (Some(ct_ends), Some(ct_starts)) = (&step.ends, &starts) {
+                // whether the first match from here is empty
+                let mut empty: Option<Ciphertext> = None;
+                for (_, ct_first) in table.exits[position][0].iter().filter(|(exit, _)| exit.test.is_none()) {
+                    self.ct_or_into(&mut empty, ct_first);
+                }
+                if let Some(ct_empty) = &empty {
+                    let ct_skipped = ct_and(self.server_key.borrow(), ct_ends, ct_empty);
+                    starts = Some(ct_and(self.server_key.borrow(), ct_starts, &ct_not(self.server_key.borrow(), &ct_skipped)));
+                }
+            }
+            let found = match (&cursor, &starts) {
+                (Some(ct_cursor), Some(ct_starts)) => Some(ct_and(self.server_key.borrow(), ct_cursor, ct_starts)),
+                _ => None,
+            };
+            if let (Some(ct_found), Some(ct_cursor)) = (&found, &cursor) {
+                // followed on its own to tell an empty match from the end of
+                // the previous one
+                let mut starting: Vec<Option<Ciphertext>> = vec![None; self.program.len() + 1];
+                starting[0] = Some(ct_found.clone());
+                let step = self.follow_first_exits(&table, position, &starting, 0);
+                for (entry, ct_next) in entries.iter_mut().zip(step.next.iter()) {
+                    if let Some(ct_next) = ct_next {
+                        self.ct_or_into(entry, ct_next);
+                    }
+                }
+                if let Some(ct_consumes) = &step.consumes {
+                    self.ct_or_into(&mut consumes, ct_consumes);
+                }
+                let mut ct_cursor = ct_and(self.server_key.borrow(), ct_cursor, &ct_not(self.server_key.borrow(), ct_found));
+                // the search goes on after an empty match
+                if let Some(ct_empty) = &step.ends {
+                    ct_cursor = ct_or(self.server_key.borrow(), &ct_cursor, ct_empty);
+                }
+                cursor = Some(ct_cursor);
+            }
+            founds.push(found);
+            if position < input.len() {
+                consumed.push(consumes);
+            }
+        }
+        (founds, consumed)
+    }
+
+    /// Counts the non-overlapping matches in the input without decrypting
+    /// anything on the way, like `machine::Machine::count_matches`.
+    ///
+    /// The counter is a binary number made of several ciphertexts, with enough
+    /// bits to count a match at every position, so it cannot overflow whatever
+    /// the length of the input.
+    pub fn count_matches(&self, input: Vec<T>) -> CipherCounter {
+        let bits = (usize::BITS - (input.len() + 1).leading_zeros()) as usize;
+        let mut counter: Vec<Ciphertext> = (0..bits).map(|_| self.server_key.borrow().create_trivial(0)).collect();
+        let (founds, _) = self.non_overlapping_matches(&input);
+        for ct_found in founds.iter().flatten() {
+            // increment the counter, carrying from the least significant bit
+            let mut ct_carry = ct_found.clone();
+            for ct_bit in counter.iter_mut() {
+                let ct_next_carry = ct_and(self.server_key.borrow(), ct_bit, &ct_carry);
+                *ct_bit = ct_xor(self.server_key.borrow(), ct_bit, &ct_carry);
+                ct_carry = ct_next_carry;
+            }
+        }
+        CipherCounter { bits: counter }
+    }
+
+    /// Encrypted masks of the characters captured by each group, group 0 being
+    /// the whole match: `masks[group][position]` encrypts 1 if the character
+    /// at `position` belongs to the span of the group in the match
+    /// `machine::Machine::captures` returns, and 0 otherwise.
+    ///
+    /// Like `run_oblivious`, nothing is decrypted on the way. The path of the
+    /// match `find_oblivious` finds is followed, recording where it saves the
+    /// bounds of each group. A repeated group keeps its last iteration: a
+    /// character is captured when the last start of the group is at or before
+    /// it and the last end after it.
+    pub fn capture_masks(&self, input: Vec<T>) -> Vec<Vec<Ciphertext>> {
+        let slots = self
+            .program
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, item)| match item.transition(pc) {
+                Transition::Save(slot) => Some(slot + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let first_match = self.first_match(&input, slots);
+        let ct_false = self.server_key.borrow().create_trivial(0);
+
+        let mut masks: Vec<Vec<Ciphertext>> = vec![first_match
+            .consumes
+            .iter()
+            .take(input.len())
+            .map(|ct_consumes| ct_consumes.clone().unwrap_or_else(|| ct_false.clone()))
+            .collect()];
+        for group in 1..slots.div_ceil(2) {
+            // whether the group starts at or before each position, and whether
+            // it starts or ends again after it
+            let mut restarted: Vec<Option<Ciphertext>> = vec![None; input.len()];
+            let mut ended: Vec<Option<Ciphertext>> = vec![None; input.len()];
+            let mut before: Option<Ciphertext> = None;
+            let started: Vec<Option<Ciphertext>> = first_match.saves[..input.len()]
+                .iter()
+                .map(|saves| {
+                    if let Some(ct_save) = &saves[2 * group] {
+                        self.ct_or_into(&mut before, ct_save);
+                    }
+                    before.clone()
+                })
+                .collect();
+            let (mut starts_after, mut ends_after): (Option<Ciphertext>, Option<Ciphertext>) = (None, None);
+            for position in (0..input.len()).rev() {
+                if let Some(ct_save) = &first_match.saves[position + 1][2 * group] {
+                    self.ct_or_into(&mut starts_after, ct_save);
+                }
+                if let Some(ct_save) = &first_match.saves[position + 1][2 * group + 1] {
+                    self.ct_or_into(&mut ends_after, ct_save);
+                }
+                restarted[position] = starts_after.clone();
+                ended[position] = ends_after.clone();
+            }
+            let mask = (0..input.len())
+                .map(|position| match (&started[position], &ended[position]) {
+                    (Some(ct_started), Some(ct_ended)) => {
+                        let ct_captured = ct_and(self.server_key.borrow(), ct_started, ct_ended);
+                        match &restarted[position] {
+                            Some(ct_restarted) => ct_and(
+                                self.server_key.borrow(),
+                                &ct_captured,
+                                &ct_not(self.server_key.borrow(), ct_restarted),
+                            ),
+                            None => ct_captured,
+                        }
+                    }
+                    _ => ct_false.clone(),
+                })
+                .collect();
+            masks.push(mask);
+        }
+        masks
+    }
+
+    // Binary encoding, least significant bit first, of the offset of the only
+    // encrypted true value among the indicators.
+    fn ct_offset(&self, indicators: &[Option<Ciphertext>]) -> Vec<Ciphertext> {
+        let max_offset = indicators.len() - 1;
+        let bits = (usize::BITS - max_offset.leading_zeros()).max(1) as usize;
+        (0..bits)
+            .map(|bit| {
+                let mut result: Option<Ciphertext> = None;
+                for (offset, indicator) in indicators.iter().enumerate() {
+                    if let (true, Some(ct_indicator)) = ((offset >> bit) & 1 == 1, indicator) {
+                        self.ct_or_into(&mut result, ct_indicator);
+                    }
+                }
+                result.unwrap_or_else(|| self.server_key.borrow().create_trivial(0))
+            })
+            .collect()
+    }
+}
+
+impl<T, I> TFHEMachine<T, I>
+where
+    T: SelectCipherTrait + Clone,
+    I: ObliviousInstruction<T>,
+{
+    /// Overwrites every character of the non-overlapping matches with the
+    /// replacement byte, like `machine::Machine::replace_all`, and returns the
+    /// encrypted result.
+    ///
+    /// Every character of the output is selected homomorphically between the
+    /// input character and the replacement, so the server doesn't learn which
+    /// positions were redacted.
+    ///
+    /// The matches are found in one pass like `find_oblivious`, for a few
+    /// bootstraps per state of the program and per position, and each
+    /// character then costs a `select`: the whole is linear in the length of
+    /// the input.
+    pub fn replace_all(&self, input: Vec<T>, replacement: u8) -> Vec<T> {
+        // whether each character belongs to a match
+        let (_, masks) = self.non_overlapping_matches(&input);
+        input
+            .into_iter()
+            .zip(masks.iter())
+            .map(|(ct_input, mask)| match mask {
+                Some(ct_mask) => ct_input.select(&self.server_key, ct_mask, replacement),
+                None => ct_input,
+            })
+            .collect()
+    }
+}
+
+/// Runs the patterns of a `CipherCompiledSet` together, in a single pass over
+/// the input.
+pub struct TFHESetMachine<T: EncodedCipherTrait + Clone> {
+    machine: TFHEMachine<T>,
+    accepts: Vec<usize>,
+    comparisons: Vec<Option<usize>>,
+}
+
+impl<T> TFHESetMachine<T>
+where
+    T: EncodedCipherTrait + Clone,
+{
+    pub fn new(set: CipherCompiledSet<T>, server_key: T::ServerKey) -> Self {
+        Self {
+            machine: TFHEMachine::new(set.program, server_key),
+            accepts: set.accepts,
+            comparisons: set.comparisons,
+        }
+    }
+
+    /// Tells, for each pattern of the set, whether it matches the input, like
+    /// `TFHEMachine::run_oblivious` does for a single pattern.
+    ///
+    /// The states of the prefixes the patterns have in common are shared, and
+    /// the instructions testing a character against the same bytes are
+    /// evaluated once per character of the input.
+    pub fn run(&self, input: Vec<T>) -> Vec<Ciphertext> {
+        let machine = &self.machine;
+        let ct_true = machine.server_key.borrow().create_trivial(1);
+        let mut states: Vec<Option<Ciphertext>> = vec![None; machine.program.len() + 1];
+        let mut results: Vec<Option<Ciphertext>> = vec![None; self.accepts.len()];
+
+        let boundaries = machine.boundaries(&input);
+        // the first instruction performing each comparison
+        let mut tests: Vec<usize> = vec![];
+        for (pc, comparison) in self.comparisons.iter().enumerate() {
+            if let Some(comparison) = comparison {
+                if *comparison == tests.len() {
+                    tests.push(pc);
+                }
+            }
+        }
+
+        for position in 0..=input.len() {
+            let at_end = position == input.len();
+            machine.activate(&mut states, 0, &ct_true, position == 0, at_end, &boundaries[position]);
+            for (result, accept) in results.iter_mut().zip(self.accepts.iter()) {
+                if let Some(ct_accept) = states[*accept].take() {
+                    machine.ct_or_into(result, &ct_accept);
+                }
+            }
+            if at_end {
+                break;
+            }
+
+            let ct_tests: Vec<Option<Ciphertext>> = tests
+                .iter()
+                .map(|pc| machine.oblivious_test(*pc, &input[position]))
+                .collect();
+            states = machine.oblivious_advance_with(
+                &states,
+                position + 1 == input.len(),
+                &boundaries[position + 1],
+                |pc| {
+                    let next = machine.oblivious_target(pc)?;
+                    let ct_test = ct_tests[self.comparisons[pc]?].clone()?;
+                    Some((ct_test, next))
+                },
+            );
+        }
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| machine.server_key.borrow().create_trivial(0)))
+            .collect()
+    }
+}
+
+pub struct TFHENfaMachine<T: EncodedCipherTrait + Clone> {
+    nfa: CipherNfa<T>,
+    server_key: T::ServerKey,
+}
+
+impl<T> TFHENfaMachine<T>
+where
+    T: EncodedCipherTrait + Clone,
+{
+    pub fn new(nfa: CipherNfa<T>, server_key: T::ServerKey) -> Self {
+        Self { nfa, server_key }
+    }
+
+    // Encrypted result of the character test of a consuming state, along with
+    // the state reached when it succeeds.
+    fn step(&self, state: usize, ct_input: &T) -> Option<(Ciphertext, usize)> {
+        match &self.nfa.states[state] {
+            CipherState::CipherChar { ct, next } => {
+                let result = ct_input.clone().equal(&self.server_key, ct.clone());
+                Some((result, *next))
+            }
+            CipherState::CipherClass { ranges, next } => {
+                Some((ct_in_ranges(&self.server_key, ct_input, ranges), *next))
+            }
+            CipherState::Split(_) | CipherState::Look { .. } | CipherState::Match => None,
+        }
+    }
+
+    /// Runs the automaton over the encrypted input without decrypting anything,
+    /// and returns an encryption of 1 if the input matches, 0 otherwise.
+    pub fn run(&self, input: Vec<T>) -> Ciphertext {
+        let ct_true = self.server_key.borrow().create_trivial(1);
+        let mut targets: Vec<Option<Ciphertext>> = vec![None; self.nfa.states.len()];
+        let mut result: Option<Ciphertext> = None;
+
+        for position in 0..=input.len() {
+            let at_start = position == 0;
+            let at_end = position == input.len();
+
+            // the pattern may start matching at any position
+            targets[self.nfa.start] = Some(ct_true.clone());
+            let mut active: Vec<Option<Ciphertext>> = vec![None; self.nfa.states.len()];
+            for (target, ct_target) in targets.iter().enumerate() {
+                if let Some(ct_target) = ct_target {
+                    for state in self.nfa.closure(target, at_start, at_end) {
+                        ct_or_into(self.server_key.borrow(), &mut active[*state], ct_target);
+                    }
+                }
+            }
+
+            targets = vec![None; self.nfa.states.len()];
+            for (state, ct_active) in active.iter().enumerate() {
+                let ct_active = match ct_active {
+                    Some(ct_active) => ct_active,
+                    None => continue,
+                };
+                if let CipherState::Match = self.nfa.states[state] {
+                    ct_or_into(self.server_key.borrow(), &mut result, ct_active);
+                } else if !at_end {
+                    if let Some((ct_result, next)) = self.step(state, &input[position]) {
+                        let ct_next = ct_and(self.server_key.borrow(), ct_active, &ct_result);
+                        ct_or_into(self.server_key.borrow(), &mut targets[next], &ct_next);
+                    }
+                }
+            }
+        }
+        result.unwrap_or_else(|| self.server_key.borrow().create_trivial(0))
+    }
+}
+
+pub struct TFHEDfaMachine<T: EncodedCipherTrait + Clone> {
+    dfa: CipherDfa<T>,
+    server_key: T::ServerKey,
+}
+
+impl<T> TFHEDfaMachine<T>
+where
+    T: EncodedCipherTrait + Clone,
+{
+    pub fn new(dfa: CipherDfa<T>, server_key: T::ServerKey) -> Self {
+        Self { dfa, server_key }
+    }
+
+    /// Runs the automaton over the encrypted input, keeping the current state as
+    /// a one-hot vector of encrypted booleans. Every character costs one range
+    /// test per byte class and one multiplication per transition of the DFA,
+    /// whatever the input is. Returns an encryption of 1 if the input matches.
+    pub fn run(&self, input: Vec<T>) -> Ciphertext {
+        let state_count = self.dfa.transitions.len();
+        let mut states: Vec<Option<Ciphertext>> = vec![None; state_count];
+        states[self.dfa.start] = Some(self.server_key.borrow().create_trivial(1));
+
+        for ct_input in input.iter() {
+            // byte classes are disjoint, exactly one of them is set
+            let ct_classes: Vec<Ciphertext> = self
+                .dfa
+                .classes
+                .iter()
+                .map(|ranges| ct_in_ranges(&self.server_key, ct_input, ranges))
+                .collect();
+
+            let mut next_states: Vec<Option<Ciphertext>> = vec![None; state_count];
+            for (state, ct_state) in states.iter().enumerate() {
+                let ct_state = match ct_state {
+                    Some(ct_state) => ct_state,
+                    None => continue,
+                };
+                for (next, ct_next_state) in next_states.iter_mut().enumerate() {
+                    if self.dfa.is_dead(next) {
+                        continue;
+                    }
+                    let classes: Vec<usize> = (0..self.dfa.classes.len())
+                        .filter(|class| self.dfa.transitions[state][*class] == next)
+                        .collect();
+                    if classes.is_empty() {
+                        continue;
+                    }
+                    if classes.len() == self.dfa.classes.len() {
+                        ct_or_into(self.server_key.borrow(), ct_next_state, ct_state);
+                        continue;
+                    }
+                    let mut ct_selected: Option<Ciphertext> = None;
+                    for class in classes {
+                        ct_or_into(self.server_key.borrow(), &mut ct_selected, &ct_classes[class]);
+                    }
+                    let ct_next = ct_and(self.server_key.borrow(), ct_state, &ct_selected.unwrap());
+                    ct_or_into(self.server_key.borrow(), ct_next_state, &ct_next);
+                }
+            }
+            states = next_states;
+        }
+
+        let mut result: Option<Ciphertext> = None;
+        for (state, ct_state) in states.iter().enumerate() {
+            if let (true, Some(ct_state)) = (self.dfa.accepting[state], ct_state) {
+                ct_or_into(self.server_key.borrow(), &mut result, ct_state);
+            }
+        }
+        result.unwrap_or_else(|| self.server_key.borrow().create_trivial(0))
+    }
+}
@@ -1,10 +1,18 @@
 use crate::{
-    compiler, program,
+    compiler, dfa, machine, nfa, program,
+    tests::{PRIORITY_CASES, SEARCH_CASES},
     tfhe_machine::{self},
     CheckerCipher,
 };
-use tfhe::shortint::prelude::*;
-use tfhe_regex::{convert_str_to_cts, EncodedCipher2bits, EncodedCipher4bits};
+use tfhe::shortint::{
+    parameters::{PARAM_MESSAGE_1_CARRY_1, PARAM_MESSAGE_2_CARRY_0, PARAM_MESSAGE_8_CARRY_0},
+    prelude::*,
+};
+use tfhe_regex::{
+    convert_str_to_class_cts, convert_str_to_cts, convert_str_to_one_hot_cts, convert_str_to_padded_cts,
+    gen_bits_keys, EncodedCipher2bits, EncodedCipher4bits, EncodedCipher8bits, EncodedCipherBits,
+    EncodedCipherClass, EncodedCipherOneHot, EncodedCipherTrait,
+};
 
 type TestEncodedCipher = EncodedCipher2bits;
 
@@ -20,7 +28,7 @@ fn get_keys() -> Result<(ClientKey, ServerKey, CheckerCipher), String> {
 #[test]
 fn simple_string() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"abc");
+    let program = compiler::Compiler::compile(r"abc").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("123abc456", &client_key);
@@ -33,7 +41,7 @@ fn simple_string() {
 #[test]
 fn simple_string_end_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"abc$");
+    let program = compiler::Compiler::compile(r"abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("123abc", &client_key);
@@ -46,7 +54,7 @@ fn simple_string_end_matching_should_succeed() {
 #[test]
 fn simple_string_end_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"abc$");
+    let program = compiler::Compiler::compile(r"abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("123abc456", &client_key);
@@ -59,7 +67,7 @@ fn simple_string_end_matching_should_fail() {
 #[test]
 fn simple_string_start_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^abc");
+    let program = compiler::Compiler::compile(r"^abc").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc123", &client_key);
@@ -72,7 +80,7 @@ fn simple_string_start_matching_should_succeed() {
 #[test]
 fn simple_string_start_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^abc");
+    let program = compiler::Compiler::compile(r"^abc").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("123abc", &client_key);
@@ -85,7 +93,7 @@ fn simple_string_start_matching_should_fail() {
 #[test]
 fn simple_string_exact_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^abc$");
+    let program = compiler::Compiler::compile(r"^abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc", &client_key);
@@ -98,7 +106,7 @@ fn simple_string_exact_matching_should_succeed() {
 #[test]
 fn simple_string_exact_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^abc$");
+    let program = compiler::Compiler::compile(r"^abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("aabc", &client_key);
@@ -111,7 +119,7 @@ fn simple_string_exact_matching_should_fail() {
 #[test]
 fn simple_string_exact_matching_should_fail_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^abc$");
+    let program = compiler::Compiler::compile(r"^abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abccc", &client_key);
@@ -124,7 +132,7 @@ fn simple_string_exact_matching_should_fail_2() {
 #[test]
 fn simple_string_one_or_more_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab+c$");
+    let program = compiler::Compiler::compile(r"^ab+c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbc", &client_key);
@@ -137,7 +145,7 @@ fn simple_string_one_or_more_matching_should_succeed() {
 #[test]
 fn simple_string_one_or_more_matching_should_succeed_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab+c$");
+    let program = compiler::Compiler::compile(r"^ab+c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc", &client_key);
@@ -150,7 +158,7 @@ fn simple_string_one_or_more_matching_should_succeed_2() {
 #[test]
 fn simple_string_one_or_more_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab+c$");
+    let program = compiler::Compiler::compile(r"^ab+c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("ac", &client_key);
@@ -163,7 +171,7 @@ fn simple_string_one_or_more_matching_should_fail() {
 #[test]
 fn simple_string_zero_or_more_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab*c$");
+    let program = compiler::Compiler::compile(r"^ab*c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("ac", &client_key);
@@ -176,7 +184,7 @@ fn simple_string_zero_or_more_matching_should_succeed() {
 #[test]
 fn simple_string_zero_or_more_matching_should_succeed_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab*c$");
+    let program = compiler::Compiler::compile(r"^ab*c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbbc", &client_key);
@@ -189,7 +197,7 @@ fn simple_string_zero_or_more_matching_should_succeed_2() {
 #[test]
 fn simple_string_optional_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab?c$");
+    let program = compiler::Compiler::compile(r"^ab?c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc", &client_key);
@@ -209,7 +217,7 @@ fn simple_string_optional_matching_should_succeed() {
 #[test]
 fn simple_string_optional_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab?c$");
+    let program = compiler::Compiler::compile(r"^ab?c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbc", &client_key);
@@ -222,7 +230,7 @@ fn simple_string_optional_matching_should_fail() {
 #[test]
 fn simple_string_numbered_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{2}c$");
+    let program = compiler::Compiler::compile(r"^ab{2}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbc", &client_key);
@@ -235,7 +243,7 @@ fn simple_string_numbered_matching_should_succeed() {
 #[test]
 fn simple_string_numbered_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{2}c$");
+    let program = compiler::Compiler::compile(r"^ab{2}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbbc", &client_key);
@@ -255,7 +263,7 @@ fn simple_string_numbered_matching_should_fail() {
 #[test]
 fn simple_string_numbered_matching_should_succeed_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{3,}c$");
+    let program = compiler::Compiler::compile(r"^ab{3,}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbbc", &client_key);
@@ -275,7 +283,7 @@ fn simple_string_numbered_matching_should_succeed_2() {
 #[test]
 fn simple_string_numbered_matching_should_fail_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{3,}c$");
+    let program = compiler::Compiler::compile(r"^ab{3,}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbc", &client_key);
@@ -288,7 +296,7 @@ fn simple_string_numbered_matching_should_fail_2() {
 #[test]
 fn simple_string_numbered_matching_should_succeed_3() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{2,4}c$");
+    let program = compiler::Compiler::compile(r"^ab{2,4}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abbbbc", &client_key);
@@ -301,7 +309,7 @@ fn simple_string_numbered_matching_should_succeed_3() {
 #[test]
 fn simple_string_numbered_matching_should_fail_3() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^ab{2,4}c$");
+    let program = compiler::Compiler::compile(r"^ab{2,4}c$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc", &client_key);
@@ -321,7 +329,7 @@ fn simple_string_numbered_matching_should_fail_3() {
 #[test]
 fn escaping_special_characters_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^\.$");
+    let program = compiler::Compiler::compile(r"^\.$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts(".", &client_key);
@@ -334,7 +342,7 @@ fn escaping_special_characters_should_succeed() {
 #[test]
 fn escaping_special_characters_should_succeed_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^\*$");
+    let program = compiler::Compiler::compile(r"^\*$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("*", &client_key);
@@ -347,7 +355,7 @@ fn escaping_special_characters_should_succeed_2() {
 #[test]
 fn character_range_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^[abc]$");
+    let program = compiler::Compiler::compile(r"^[abc]$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("a", &client_key);
@@ -359,11 +367,11 @@ fn character_range_matching_should_succeed() {
 
 #[test]
 fn character_range_matching_should_fail() {
-    // let program = Compiler::compile(r"^[abc]$");
+    // let program = Compiler::compile(r"^[abc]$").unwrap();
     // let mut machine = Machine::new(program);
     // assert!(!machine.run("d".to_string()));
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^[abc]$");
+    let program = compiler::Compiler::compile(r"^[abc]$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("d", &client_key);
@@ -376,7 +384,7 @@ fn character_range_matching_should_fail() {
 #[test]
 fn character_range_not_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^[^ade]$");
+    let program = compiler::Compiler::compile(r"^[^ade]$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("b", &client_key);
@@ -389,7 +397,7 @@ fn character_range_not_matching_should_succeed() {
 #[test]
 fn character_range_not_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^[^ade]$");
+    let program = compiler::Compiler::compile(r"^[^ade]$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("a", &client_key);
@@ -402,7 +410,7 @@ fn character_range_not_matching_should_fail() {
 #[test]
 fn any_character_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^.$");
+    let program = compiler::Compiler::compile(r"^.$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("A", &client_key);
@@ -415,7 +423,7 @@ fn any_character_matching_should_succeed() {
 #[test]
 fn case_insensitive_argument_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"(?i)^abc$");
+    let program = compiler::Compiler::compile(r"(?i)^abc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("ABC", &client_key);
@@ -428,7 +436,7 @@ fn case_insensitive_argument_should_succeed() {
 #[test]
 fn alternation_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"0a|bcd$");
+    let program = compiler::Compiler::compile(r"0a|bcd$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("0a", &client_key);
@@ -448,7 +456,7 @@ fn alternation_should_succeed() {
 #[test]
 fn alternation_should_succeed_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"a(bc|ed)42$");
+    let program = compiler::Compiler::compile(r"a(bc|ed)42$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abc42", &client_key);
@@ -468,7 +476,7 @@ fn alternation_should_succeed_2() {
 #[test]
 fn alternation_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"0a|bcd$");
+    let program = compiler::Compiler::compile(r"0a|bcd$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("0b", &client_key);
@@ -488,7 +496,7 @@ fn alternation_should_fail() {
 #[test]
 fn alternation_should_fail_2() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"a(bc|ed)42$");
+    let program = compiler::Compiler::compile(r"a(bc|ed)42$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("abd42", &client_key);
@@ -508,7 +516,7 @@ fn alternation_should_fail_2() {
 #[test]
 fn alternation_string_numbered_matching_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$");
+    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("helabbbc", &client_key);
@@ -528,7 +536,7 @@ fn alternation_string_numbered_matching_should_succeed() {
 #[test]
 fn alternation_string_numbered_matching_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$");
+    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("helabbc", &client_key);
@@ -549,7 +557,7 @@ fn alternation_string_numbered_matching_should_fail() {
 fn repetition_with_range_should_succeed() {
     let (client_key, server_key, checker) = get_keys().unwrap();
 
-    let program = compiler::Compiler::compile(r"^01[b-e]{4}56$");
+    let program = compiler::Compiler::compile(r"^01[b-e]{4}56$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("01bbbb56", &client_key);
@@ -569,7 +577,7 @@ fn repetition_with_range_should_succeed() {
 #[test]
 fn repetition_with_range_should_fail() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^01[b-e]{4}56$");
+    let program = compiler::Compiler::compile(r"^01[b-e]{4}56$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("01bb56", &client_key);
@@ -589,7 +597,7 @@ fn repetition_with_range_should_fail() {
 #[test]
 fn repetition_with_range_should_succeed_1() {
     let (client_key, server_key, checker) = get_keys().unwrap();
-    let program = compiler::Compiler::compile(r"^hel(a[b-e]{2}|[l-n]{3,}o)bc$");
+    let program = compiler::Compiler::compile(r"^hel(a[b-e]{2}|[l-n]{3,}o)bc$").unwrap();
     let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
 
     let input = convert_str_to_cts("helacdbc", &client_key);
@@ -605,3 +613,987 @@ fn repetition_with_range_should_succeed_1() {
     let result = machine.run(input, &checker);
     assert!(result);
 }
+
+#[test]
+fn oblivious_simple_string() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"abc").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    let input = convert_str_to_cts("12abc4", &client_key);
+    let result = machine.run_oblivious(input);
+    assert_eq!(client_key.decrypt(&result), 1);
+
+    let input = convert_str_to_cts("12ab4c", &client_key);
+    let result = machine.run_oblivious(input);
+    assert_eq!(client_key.decrypt(&result), 0);
+}
+
+#[test]
+fn oblivious_exact_matching() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^abc$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    let input = convert_str_to_cts("abc", &client_key);
+    let result = machine.run_oblivious(input);
+    assert_eq!(client_key.decrypt(&result), 1);
+
+    let input = convert_str_to_cts("aabc", &client_key);
+    let result = machine.run_oblivious(input);
+    assert_eq!(client_key.decrypt(&result), 0);
+
+    let input = convert_str_to_cts("abcc", &client_key);
+    let result = machine.run_oblivious(input);
+    assert_eq!(client_key.decrypt(&result), 0);
+}
+
+#[test]
+fn oblivious_repetitions() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^ab*c?d{2,3}$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [("abbcdd", 1), ("addd", 1), ("abcd", 0), ("abdddd", 0)] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn oblivious_range_repetition() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^[a-c]+a$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [("ba", 1), ("cbca", 1), ("a", 0), ("bd", 0)] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn oblivious_alternation() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^hel(ab{2}|l{3,}o)bc$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [
+        ("helabbbc", 1),
+        ("helllllllobc", 1),
+        ("helabbc", 0),
+        ("helllobc", 0),
+    ] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn multiline_anchors_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"(?m)^foo$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("bar\nfoo\nbaz", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(result);
+}
+
+#[test]
+fn multiline_anchors_should_fail() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"(?m)^foo$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("barfoo\nbaz", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(!result);
+}
+
+#[test]
+fn oblivious_multiline_anchors() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"(?m)^fo+$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [
+        ("foo", 1),
+        ("bar\nfoo\nbaz", 1),
+        ("bar\nfoo", 1),
+        ("barfoo\nbaz", 0),
+        ("bar\nfoox", 0),
+        ("\n", 0),
+    ] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn word_boundary_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"\bcat\b").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("the cat sat", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(result);
+}
+
+#[test]
+fn word_boundary_should_fail() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"\bcat\b").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("concatenate", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(!result);
+}
+
+#[test]
+fn oblivious_word_boundary() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in [
+        (
+            r"\bcat\b",
+            vec![("cat", 1), ("the cat sat", 1), ("(cat)", 1), ("concatenate", 0), ("cats", 0)],
+        ),
+        (r"\Bat\b", vec![("cat", 1), ("at", 0), ("a at", 0)]),
+        (r"^\B$", vec![("", 1), ("a", 0)]),
+        (r"^\b$", vec![("", 0)]),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+        for (input, expected) in inputs {
+            let result = machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn byte_class_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"(?-u)^a[\x80-\xff]{2}b$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("a\u{e9}b", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(result);
+}
+
+#[test]
+fn oblivious_byte_class() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"(?-u)[\x80-\xff]+z").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [("a\u{e9}z", 1), ("az", 0), ("\u{e9}", 0)] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn unicode_class_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^[\x{3b1}-\x{3c9}]+$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("\u{3bb}\u{3bf}\u{3b3}\u{3bf}\u{3c2}", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(result);
+}
+
+#[test]
+fn oblivious_unicode() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"caf\x{e9} [x\x{e9}\x{20ac}\x{1f600}]").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [
+        ("un caf\u{e9} \u{20ac}", 1),
+        ("caf\u{e9} \u{1f600}", 1),
+        ("caf\u{e9} x", 1),
+        ("cafe \u{20ac}", 0),
+        ("caf\u{e9} \u{e8}", 0),
+    ] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn nfa_machine_agrees_with_backtracking_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in [
+        (r"^ab?c$", vec!["abc", "ac", "abbc"]),
+        (r"a(bc|ed)42$", vec!["abc42", "aed42", "abd42"]),
+        (r"^01[b-e]{2}5$", vec!["01bc5", "01bf5"]),
+        (r"^caf\x{e9}$", vec!["caf\u{e9}", "caf\u{e8}"]),
+    ] {
+        let nfa = compiler::Compiler::compile_nfa(pattern).unwrap();
+        let nfa = nfa::cipher_nfa::<TestEncodedCipher>(&client_key, nfa);
+        let tfhe_machine = tfhe_machine::TFHENfaMachine::new(nfa, server_key.clone());
+
+        for input in inputs {
+            let mut machine = machine::Machine::new(compiler::Compiler::compile(pattern).unwrap());
+            let expected = machine.run(input.to_string());
+
+            let result = tfhe_machine.run(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected);
+        }
+    }
+}
+
+#[test]
+fn nfa_machine_repetition_of_group() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let nfa = compiler::Compiler::compile_nfa(r"^(ab)+c$").unwrap();
+    let nfa = nfa::cipher_nfa::<TestEncodedCipher>(&client_key, nfa);
+    let machine = tfhe_machine::TFHENfaMachine::new(nfa, server_key);
+
+    for (input, expected) in [("abc", 1), ("ababc", 1), ("abac", 0)] {
+        let result = machine.run(convert_str_to_cts(input, &client_key));
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn dfa_machine_simple_string() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let dfa = compiler::Compiler::compile_dfa(r"abc").unwrap();
+    let dfa = dfa::cipher_dfa::<TestEncodedCipher>(&client_key, dfa);
+    let machine = tfhe_machine::TFHEDfaMachine::new(dfa, server_key);
+
+    for (input, expected) in [("123abc456", 1), ("aabc", 1), ("ab", 0), ("acb", 0)] {
+        let result = machine.run(convert_str_to_cts(input, &client_key));
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn dfa_machine_agrees_with_backtracking_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in [
+        (r"^ab?c$", vec!["abc", "ac", "abbc"]),
+        (r"a(bc|ed)42$", vec!["abc42", "aed42", "abd42"]),
+        (r"^hel(ab{2}|l{3,}o)bc$", vec!["helabbbc", "helllllllobc", "helllobc"]),
+        (r"^[\x{3b1}-\x{3c9}]+$", vec!["\u{3bb}\u{3bf}", "\u{391}"]),
+    ] {
+        let dfa = compiler::Compiler::compile_dfa(pattern).unwrap();
+        let dfa = dfa::cipher_dfa::<TestEncodedCipher>(&client_key, dfa);
+        let tfhe_machine = tfhe_machine::TFHEDfaMachine::new(dfa, server_key.clone());
+
+        for input in inputs {
+            let mut machine = machine::Machine::new(compiler::Compiler::compile(pattern).unwrap());
+            let expected = machine.run(input.to_string());
+
+            let result = tfhe_machine.run(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected);
+        }
+    }
+}
+
+#[test]
+fn group_repetition_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(ab)+c$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("ababc", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(result);
+}
+
+#[test]
+fn group_repetition_should_fail() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(ab)+c$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("abac", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(!result);
+}
+
+#[test]
+fn alternation_repetition_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(a|b){2,3}c$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let input = convert_str_to_cts("bac", &client_key);
+
+    let mut machine = tfhe_machine::TFHEMachine::new(program.clone(), server_key.clone());
+    let result = machine.run(input, &checker);
+    assert!(result);
+
+    let input = convert_str_to_cts("ababc", &client_key);
+    let mut machine = tfhe_machine::TFHEMachine::new(program, server_key);
+    let result = machine.run(input, &checker);
+    assert!(!result);
+}
+
+#[test]
+fn oblivious_group_repetition() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^x(ab)*$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [("x", 1), ("xabab", 1), ("xaba", 0)] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn three_way_alternation_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(ab|cd|ef)g$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    for input in ["abg", "cdg", "efg"] {
+        let input = convert_str_to_cts(input, &client_key);
+        let mut machine = tfhe_machine::TFHEMachine::new(program.clone(), server_key.clone());
+        let result = machine.run(input, &checker);
+        assert!(result);
+    }
+}
+
+#[test]
+fn three_way_alternation_should_fail() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(ab|cd|ef)g$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    for input in ["adg", "eg"] {
+        let input = convert_str_to_cts(input, &client_key);
+        let mut machine = tfhe_machine::TFHEMachine::new(program.clone(), server_key.clone());
+        let result = machine.run(input, &checker);
+        assert!(!result);
+    }
+}
+
+#[test]
+fn nested_alternation_should_succeed() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(a|(b|c)d)e$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    for input in ["ae", "bde", "cde"] {
+        let input = convert_str_to_cts(input, &client_key);
+        let mut machine = tfhe_machine::TFHEMachine::new(program.clone(), server_key.clone());
+        let result = machine.run(input, &checker);
+        assert!(result);
+    }
+}
+
+#[test]
+fn nested_alternation_should_fail() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(a|(b|c)d)e$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    for input in ["be", "ade", "cdd"] {
+        let input = convert_str_to_cts(input, &client_key);
+        let mut machine = tfhe_machine::TFHEMachine::new(program.clone(), server_key.clone());
+        let result = machine.run(input, &checker);
+        assert!(!result);
+    }
+}
+
+#[test]
+fn oblivious_nested_alternation() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"^(a|(b|c)d)e$").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [("ae", 1), ("bde", 1), ("cde", 1), ("be", 0), ("ade", 0)] {
+        let input = convert_str_to_cts(input, &client_key);
+        let result = machine.run_oblivious(input);
+        assert_eq!(client_key.decrypt(&result), expected);
+    }
+}
+
+#[test]
+fn tfhe_machine_agrees_with_regex_crate() {
+    let (client_key, server_key, checker) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let mut machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = regex.is_match(input.as_bytes());
+            let result = machine.run(convert_str_to_cts(input, &client_key), &checker);
+            assert_eq!(result, expected, "{} on {:?}", pattern, input);
+            let result = machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn oblivious_find() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let program = compiler::Compiler::compile(r"b+c?").unwrap();
+    let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+    let machine = tfhe_machine::TFHEMachine::new(program, server_key);
+
+    for (input, expected) in [
+        ("abbbcbb", Some((1, 5))),
+        ("aaaaaaaaab", Some((9, 10))),
+        ("b", Some((0, 1))),
+        ("ac", None),
+        ("", None),
+    ] {
+        let span = machine.find_oblivious(convert_str_to_cts(input, &client_key));
+        assert_eq!(span.decrypt(&client_key), expected, "{:?}", input);
+    }
+}
+
+#[test]
+fn oblivious_find_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = machine.find(input.to_string());
+            let span = tfhe_machine.find_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(span.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn oblivious_find_agrees_with_regex_crate() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = regex.find(input.as_bytes()).map(|found| (found.start(), found.end()));
+            let span = tfhe_machine.find_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(span.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn oblivious_capture_masks() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, input, expected) in [
+        (r"^[a-z]+@([a-z.]+)$", "bob@mail.com", vec!["111111111111", "000011111111"]),
+        (r"^[a-z]+@([a-z.]+)$", "bob.mail.com", vec!["000000000000", "000000000000"]),
+        (r"(a)|(b)", "xbay", vec!["0100", "0000", "0100"]),
+        (r"^(ab)+", "ababx", vec!["11110", "00110"]),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        let masks: Vec<String> = machine
+            .capture_masks(convert_str_to_cts(input, &client_key))
+            .iter()
+            .map(|mask| {
+                mask.iter()
+                    .map(|ct_bit| char::from(b'0' + client_key.decrypt(ct_bit) as u8))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(masks, expected, "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn oblivious_capture_masks_agree_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let groups = machine.captures(input.to_string()).unwrap_or_default();
+            let masks = tfhe_machine.capture_masks(convert_str_to_cts(input, &client_key));
+            for (group, mask) in masks.iter().enumerate() {
+                let span = groups.get(group).copied().flatten();
+                let expected: Vec<u64> = (0..input.len())
+                    .map(|position| matches!(span, Some((start, end)) if start <= position && position < end) as u64)
+                    .collect();
+                let mask: Vec<u64> = mask.iter().map(|ct_bit| client_key.decrypt(ct_bit)).collect();
+                assert_eq!(mask, expected, "group {} of {} on {:?}", group, pattern, input);
+            }
+        }
+    }
+}
+
+#[test]
+fn oblivious_count_matches() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, input, expected) in [
+        (r"cat", "cat concat", 2),
+        (r"[0-9]", "a1b22c3", 4),
+        (r"aa", "aaaaa", 2),
+        (r"a*", "baa", 2),
+        (r"x", "abc", 0),
+        (r"a", "aaaaaaaa", 8),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+        let counter = machine.count_matches(convert_str_to_cts(input, &client_key));
+        assert_eq!(counter.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+    }
+}
+
+// the counter adds match bits with lookup tables on their sum, which must not
+// wrap around a message space of a single bit
+#[test]
+fn oblivious_count_matches_with_one_bit_messages() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_1_CARRY_1);
+    for (pattern, input, expected) in [(r"a", "aaa", 3), (r"ab", "abxab", 2), (r"x", "abc", 0)] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<EncodedCipherBits>(&client_key, program);
+        let machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+        let counter = machine.count_matches(convert_str_to_cts(input, &client_key));
+        assert_eq!(counter.decrypt(&client_key.shortint), expected, "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn oblivious_count_matches_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = machine.count_matches(input.to_string());
+            let counter = tfhe_machine.count_matches(convert_str_to_cts(input, &client_key));
+            assert_eq!(counter.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn oblivious_replace_all() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, input, expected) in [
+        (r"[0-9]", "pin 1234", "pin ****"),
+        (r"[a-z]+@[a-z.]+", "to bob@x.io", "to ********"),
+        (r"aa", "aaaaa", "****a"),
+        (r"z", "abc", "abc"),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        let output: Vec<u8> = machine
+            .replace_all(convert_str_to_cts(input, &client_key), b'*')
+            .into_iter()
+            .map(|ct| ct.decrypt(&client_key))
+            .collect();
+        assert_eq!(output, expected.as_bytes(), "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn oblivious_replace_all_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = machine.replace_all(input.to_string(), b'#');
+            let output: Vec<u8> = tfhe_machine
+                .replace_all(convert_str_to_cts(input, &client_key), b'#')
+                .into_iter()
+                .map(|ct| ct.decrypt(&client_key))
+                .collect();
+            assert_eq!(output, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn oblivious_replace_all_agrees_with_regex_crate() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES.iter().chain(PRIORITY_CASES) {
+        let regex = regex::bytes::Regex::new(pattern).unwrap();
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let mut expected = input.as_bytes().to_vec();
+            for found in regex.find_iter(input.as_bytes()) {
+                expected[found.start()..found.end()].fill(b'#');
+            }
+            let output: Vec<u8> = tfhe_machine
+                .replace_all(convert_str_to_cts(input, &client_key), b'#')
+                .into_iter()
+                .map(|ct| ct.decrypt(&client_key))
+                .collect();
+            assert_eq!(output, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn set_machine_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let patterns: Vec<&str> = SEARCH_CASES.iter().map(|(pattern, _)| *pattern).collect();
+    let set = compiler::Compiler::compile_set(&patterns).unwrap();
+    let set = program::cipher_set::<TestEncodedCipher>(&client_key, set);
+    let set_machine = tfhe_machine::TFHESetMachine::new(set, server_key);
+
+    let mut machines: Vec<machine::Machine> = patterns
+        .iter()
+        .map(|pattern| machine::Machine::new(compiler::Compiler::compile(pattern).unwrap()))
+        .collect();
+    for (_, inputs) in SEARCH_CASES {
+        for input in inputs.iter() {
+            let results = set_machine.run(convert_str_to_cts(input, &client_key));
+            assert_eq!(results.len(), patterns.len());
+            for ((pattern, machine), ct_result) in patterns.iter().zip(machines.iter_mut()).zip(results.iter()) {
+                let expected = machine.run(input.to_string());
+                assert_eq!(client_key.decrypt(ct_result) == 1, expected, "{} on {:?}", pattern, input);
+            }
+        }
+    }
+}
+
+#[test]
+fn plaintext_input_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = machine.run(input.to_string());
+            let result = tfhe_machine.run_oblivious_on_plaintext(input.as_bytes());
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn plaintext_input_agrees_with_encrypted_input() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let encrypted = tfhe_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            let clear = tfhe_machine.run_oblivious_on_plaintext(input.as_bytes());
+            assert_eq!(client_key.decrypt(&clear), client_key.decrypt(&encrypted), "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn plaintext_input_with_private_pattern() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    let document = b"Meeting notes: ship release 2.4 on friday";
+    for (pattern, expected) in [
+        (r"release [0-9]\.[0-9]", true),
+        (r"\bfri(day)?\b", true),
+        (r"(?i)MEETING", true),
+        (r"release [0-9]\.[5-9]", false),
+        (r"^notes", false),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+        let result = tfhe_machine.run_oblivious_on_plaintext(document);
+        assert_eq!(client_key.decrypt(&result) == 1, expected, "{}", pattern);
+    }
+}
+
+#[test]
+fn clear_program_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::clear_program(program);
+        let tfhe_machine =
+            tfhe_machine::TFHEMachine::<TestEncodedCipher, _>::with_clear_program(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let result = tfhe_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            let expected = machine.run(input.to_string());
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+
+            let span = tfhe_machine.find_oblivious(convert_str_to_cts(input, &client_key));
+            let expected = machine.find(input.to_string());
+            assert_eq!(span.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn clear_program_agrees_with_cipher_program() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let cipher_machine = tfhe_machine::TFHEMachine::new(
+            program::cipher_program::<TestEncodedCipher>(&client_key, program.clone()),
+            server_key.clone(),
+        );
+        let clear_machine = tfhe_machine::TFHEMachine::<TestEncodedCipher, _>::with_clear_program(
+            program::clear_program(program),
+            server_key.clone(),
+        );
+
+        for input in inputs.iter() {
+            let cipher_result = cipher_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            let clear_result = clear_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(
+                client_key.decrypt(&clear_result),
+                client_key.decrypt(&cipher_result),
+                "{} on {:?}",
+                pattern,
+                input
+            );
+        }
+    }
+}
+
+#[test]
+fn padded_input_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+
+        for input in inputs.iter() {
+            let expected = machine.run(input.to_string());
+            let padded = convert_str_to_padded_cts(input, 10, &client_key);
+            let result = tfhe_machine.run_oblivious_padded(&padded);
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn padded_input_hides_the_end() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, input, expected) in [
+        (r"abc$", "xabc", true),
+        (r"abc$", "abcx", false),
+        (r"^$", "", true),
+        (r"a\b", "a", true),
+        (r"(?m)c$", "abc", true),
+        (r"x*$", "ab", true),
+        (r"ab\x00", "ab", false),
+    ] {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let program = program::cipher_program::<TestEncodedCipher>(&client_key, program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(program, server_key.clone());
+        let padded = convert_str_to_padded_cts(input, 6, &client_key);
+        let result = tfhe_machine.run_oblivious_padded(&padded);
+        assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+    }
+}
+
+#[test]
+fn eight_bits_encoding_agrees_with_machine() {
+    // no carry space: the machine must not use any bivariate lookup table
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_8_CARRY_0);
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let cipher_program = program::cipher_program::<EncodedCipher8bits>(&client_key, program.clone());
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(cipher_program, server_key.clone());
+        let clear_machine = tfhe_machine::TFHEMachine::<EncodedCipher8bits, _>::with_clear_program(
+            program::clear_program(program),
+            server_key.clone(),
+        );
+
+        for input in inputs.iter() {
+            let expected = machine.run(input.to_string());
+            let result = tfhe_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+            let result = clear_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+
+            let span = tfhe_machine.find_oblivious(convert_str_to_cts(input, &client_key));
+            let expected = machine.find(input.to_string());
+            assert_eq!(span.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn one_hot_encoding_agrees_with_machine() {
+    let (client_key, server_key, _) = get_keys().unwrap();
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let cipher_program = program::cipher_program::<EncodedCipherOneHot>(&client_key, program.clone());
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(cipher_program, server_key.clone());
+        let clear_machine = tfhe_machine::TFHEMachine::<EncodedCipherOneHot, _>::with_clear_program(
+            program::clear_program(program),
+            server_key.clone(),
+        );
+
+        for input in inputs.iter() {
+            let expected = machine.run(input.to_string());
+            let result = tfhe_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+            // only the bytes of the input
+            let alphabet = input.as_bytes();
+            let result = clear_machine.run_oblivious(convert_str_to_one_hot_cts(input, alphabet, &client_key));
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn bits_encoding_agrees_with_machine() {
+    let (client_key, server_key) = gen_bits_keys(PARAM_MESSAGE_2_CARRY_2);
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let cipher_program = program::cipher_program::<EncodedCipherBits>(&client_key, program.clone());
+        let tfhe_machine = tfhe_machine::TFHEMachine::new(cipher_program, server_key.clone());
+        let clear_machine = tfhe_machine::TFHEMachine::<EncodedCipherBits, _>::with_clear_program(
+            program::clear_program(program),
+            server_key.clone(),
+        );
+
+        for input in inputs.iter() {
+            let expected = machine.run(input.to_string());
+            let result = tfhe_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.shortint.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+            let result = clear_machine.run_oblivious(convert_str_to_cts(input, &client_key));
+            assert_eq!(client_key.shortint.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+
+            // the match bits back to boolean ciphertexts
+            let expected = machine.replace_all(input.to_string(), b'#');
+            let output: Vec<u8> = tfhe_machine
+                .replace_all(convert_str_to_cts(input, &client_key), b'#')
+                .into_iter()
+                .map(|ct| ct.decrypt(&client_key))
+                .collect();
+            assert_eq!(output, expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn byte_classes_agree_with_machine() {
+    // room for any number of classes
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_8_CARRY_0);
+    for (pattern, inputs) in SEARCH_CASES {
+        let program = compiler::Compiler::compile(pattern).unwrap();
+        let mut machine = machine::Machine::new(program.clone());
+        let classes = program::ByteClasses::new(&program);
+        let tfhe_machine = tfhe_machine::TFHEMachine::<EncodedCipherClass, _>::with_clear_program(
+            classes.clear_program(program),
+            server_key.clone(),
+        );
+
+        for input in inputs.iter() {
+            let result = tfhe_machine.run_oblivious(convert_str_to_class_cts(input, &classes.map, &client_key));
+            let expected = machine.run(input.to_string());
+            assert_eq!(client_key.decrypt(&result) == 1, expected, "{} on {:?}", pattern, input);
+
+            let span = tfhe_machine.find_oblivious(convert_str_to_class_cts(input, &classes.map, &client_key));
+            let expected = machine.find(input.to_string());
+            assert_eq!(span.decrypt(&client_key), expected, "{} on {:?}", pattern, input);
+        }
+    }
+}
+
+#[test]
+fn byte_classes_in_small_message_space() {
+    let program = compiler::Compiler::compile(r"[a-z]+@[a-z]+\.[a-z]+").unwrap();
+    let classes = program::ByteClasses::new(&program);
+    // lowercase letters, @, . and everything else
+    assert_eq!(classes.bits(), 2);
+    let (client_key, server_key) = gen_keys(PARAM_MESSAGE_2_CARRY_0);
+    let tfhe_machine = tfhe_machine::TFHEMachine::<EncodedCipherClass, _>::with_clear_program(
+        classes.clear_program(program),
+        server_key,
+    );
+    for (input, expected) in [
+        ("mail alice@example.org now", true),
+        ("alice@example", false),
+        ("@x.y", false),
+        ("Bob b@c.de", true),
+    ] {
+        let result = tfhe_machine.run_oblivious(convert_str_to_class_cts(input, &classes.map, &client_key));
+        assert_eq!(client_key.decrypt(&result) == 1, expected, "{:?}", input);
+    }
+}